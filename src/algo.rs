@@ -1,250 +1,1561 @@
-use crate::{Iteration, Options, Target};
-use crate::utils::{randsign, norm, norm2, cosine, nz};
+use crate::{BatchTarget, Control, Iteration, Method, Moments, Options, ParamKind, Perturbation, Preconditioner, Report, Role, SpsaError, Status, StopReason, Target};
+use crate::utils::{perturb, norm, norm2, cosine, nz, seeded, clamp, jacobi};
 use crate::vec::op;
 
-use rand::{SeedableRng, thread_rng};
-use rand::rngs::StdRng;
+use rand::RngCore;
+use rand_chacha::ChaCha12Rng;
 
-pub const REGISTER_NUM: usize = 8;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-pub fn optimize<T: Target>(mut target: T, options: Options, x: &mut [f64], r: &mut [Vec<f64>; REGISTER_NUM]) {
-    let size = x.len();
-    let Options {
-        adam,
-        iterations,
-        lr, lr_decay, lr_power,
-        px, px_decay, px_power,
-        momentum, beta, epsilon
-    } = options;
+pub const REGISTER_NUM: usize = 10;
+pub const BATCH_MAX: usize = 4;
 
-    let (r0, r) = r.split_first_mut().unwrap();
-    let (r1, r) = r.split_first_mut().unwrap();
-    let (r2, r) = r.split_first_mut().unwrap();
-    let (r3, r) = r.split_first_mut().unwrap();
-    let (r4, r) = r.split_first_mut().unwrap();
-    let (r5, r) = r.split_first_mut().unwrap();
-    let (r6, r) = r.split_first_mut().unwrap();
-    let (r7, _) = r.split_first_mut().unwrap();
+/// Perturbation size of one-sided gradient estimates relative to two-sided ones, whose curvature terms cancel out
+const ONE_SIDED_PX: f64 = 0.02;
 
-    let mut rng = StdRng::from_rng(thread_rng()).unwrap();
+/// Largest growth of the step from one iteration to the next with one-sided gradients or without line search
+const STEP_GROWTH: f64 = 2.0;
 
-    let mut m1 = 1.0 - momentum;
-    let m2 = 1.0 - beta;
-    let mut bn = 0.0;
-    let mut y = 0.0;
-    let mut noise = 0.0;
+/// Largest growth of the step from one iteration to the next with two-sided gradients and line search,
+/// whose probes keep the learning rate in check but can't stop a single wrong gradient estimate
+const PROBED_STEP_GROWTH: f64 = 16.0;
 
-    for _ in 0..(f64::sqrt(size as f64 + 100.0) as i32) {
-        let temp = target.evaluate(x);
-        bn += m2 * (1.0 - bn);
-        y += m2 * (temp - y);
-        noise += m2 * (f64::powi(temp - target.evaluate(x), 2) - noise);
+/// Point the optimizer asks to evaluate
+#[derive(Clone, Copy)]
+enum Query {
+    X,
+    P1,
+    P2
+}
+
+/// Stage of the optimization process the state machine is at
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+enum Phase {
+    WarmUp(usize),
+    Gradient(usize),
+    LrSearch(usize),
+    Start,
+    Perturb(usize),
+    Curvature(usize),
+    LineSearch,
+    Step,
+    Rollback,
+    Iterated,
+    Finish,
+    Done,
+    Failed
+}
+
+impl Phase {
+    /// Points to evaluate in this phase; [deterministic](Options#structfield.deterministic) targets
+    /// skip the repeated evaluations used for noise estimation
+    fn queries(self, options: &Options) -> &'static [Query] {
+        match self {
+            Phase::WarmUp(_) | Phase::Start | Phase::Step | Phase::Rollback | Phase::Finish if options.deterministic => &[Query::X],
+            Phase::WarmUp(_) | Phase::Start | Phase::Step | Phase::Rollback | Phase::Finish => &[Query::X, Query::X],
+            Phase::Perturb(_) if options.one_sided => &[Query::P1],
+            Phase::Gradient(_) | Phase::Perturb(_) | Phase::Curvature(_) | Phase::LineSearch => &[Query::P1, Query::P2],
+            Phase::LrSearch(_) if options.deterministic => &[Query::P1, Query::X],
+            Phase::LrSearch(_) => &[Query::P1, Query::P1, Query::X, Query::X],
+            Phase::Iterated | Phase::Done | Phase::Failed => &[]
+        }
     }
+}
+
+/// Step-by-step SPSA optimizer
+///
+/// Unlike [Optimizer::optimize()](crate::Optimizer::optimize()), which pulls function values through [Target],
+/// this lets the caller decide when and how the function gets evaluated:
+/// [ask](SpsaState::ask()) for a point, evaluate it, then [tell](SpsaState::tell()) the optimizer the result.
+///
+/// # Example
+/// ```rust
+/// use approx::assert_relative_eq;
+/// use spsa::{Options, SpsaState};
+///
+/// let mut state = SpsaState::new(&[0.0, 0.0], Options::default()).unwrap();
+///
+/// while let Some(point) = state.ask() {
+///     let value = 1.0 - (point[0] + 1.0) * (point[0] + 1.0) - (point[1] - 1.0) * (point[1] - 1.0);
+///     state.tell(value).unwrap();
+/// }
+///
+/// assert_relative_eq!(state.point()[0], -1.0, epsilon = 1e-6);
+/// assert_relative_eq!(state.point()[1],  1.0, epsilon = 1e-6);
+/// ```
+pub struct SpsaState<R = ChaCha12Rng> {
+    options: Options,
+    r: [Vec<f64>; REGISTER_NUM],
+    rng: R,
+
+    phase: Phase,
+    values: [f64; BATCH_MAX],
+    told: usize,
+    seed: u64,
+    draws: usize,
+
+    warmup: usize,
+    m1: f64,
+    m2: f64,
+    b1: f64,
+    b2: f64,
+    bn: f64,
+    y: f64,
+    noise: f64,
+    lr: f64,
+    mx: f64,
+    bx: f64,
+    y_best: f64,
+    y3: f64,
+    y6: f64,
+    step: f64,
+    dxx: f64,
+    value: f64,
+
+    hessian: Vec<f64>,
+    tdx: Vec<f64>,
+    hn: usize,
+    curvature: f64,
+    y1: f64,
+    y2: f64,
+    gradient_sum: Vec<f64>,
+
+    momentum_fails: usize,
+    consecutive_fails: usize,
+    improvement_fails: usize,
+
+    i: usize,
+    evaluations: usize,
+    stop: StopReason,
+
+    diag: Vec<f64>,
+    scratch: Vec<f64>
+}
+
+/// Memory reused by consecutive optimizations of an [Optimizer](crate::Optimizer)
+#[derive(Default, Clone)]
+pub(crate) struct Registers {
+    r: [Vec<f64>; REGISTER_NUM],
+    hessian: Vec<f64>,
+    scratch: Vec<f64>,
+    tdx: Vec<f64>,
+    diag: Vec<f64>,
+    gradient_sum: Vec<f64>
+}
+
+/// Snapshot of the full optimizer state, used to resume the optimization later
+///
+/// Serializable with the `serde` feature.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Checkpoint<R = ChaCha12Rng> {
+    r: [Vec<f64>; REGISTER_NUM],
+    rng: R,
 
-    if y.is_nan() { // initial point cannot be nan
-        x.fill(f64::NAN);
-        return;
+    phase: Phase,
+    values: [f64; BATCH_MAX],
+    told: usize,
+    seed: u64,
+    draws: usize,
+
+    warmup: usize,
+    m1: f64,
+    m2: f64,
+    b1: f64,
+    b2: f64,
+    bn: f64,
+    y: f64,
+    noise: f64,
+    lr: f64,
+    mx: f64,
+    bx: f64,
+    y_best: f64,
+    y3: f64,
+    y6: f64,
+    step: f64,
+    dxx: f64,
+    value: f64,
+
+    hessian: Vec<f64>,
+    tdx: Vec<f64>,
+    hn: usize,
+    curvature: f64,
+    y1: f64,
+    y2: f64,
+    gradient_sum: Vec<f64>,
+
+    momentum_fails: usize,
+    consecutive_fails: usize,
+    improvement_fails: usize,
+
+    i: usize,
+    evaluations: usize,
+    stop: StopReason
+}
+
+impl SpsaState {
+
+    /// Begin SPSA optimization starting at `point`.
+    /// Returns a [SpsaError] if the options are invalid.
+    pub fn new(point: &[f64], options: Options) -> Result<Self, SpsaError> {
+        let rng = seeded(options.seed);
+        Self::with_rng(point, options, rng)
     }
+}
 
-    let mut b1 = 0.0;
-    let mut b2 = 0.0;
+impl<R: RngCore + Clone> SpsaState<R> {
 
-    let gx = r0;
-    let slow_gx = r1;
-    let square_gx = r2;
+    /// Capture the full optimizer state, including the random number generator
+    pub fn checkpoint(&self) -> Checkpoint<R> {
+        Checkpoint {
+            r: self.r.clone(),
+            rng: self.rng.clone(),
 
-    for i in 0..(f64::sqrt(size as f64 + 100.0) as i32) {
-        let dx = op!(mut r3 => randsign(&mut rng) / (1.0 + i as f64));
+            phase: self.phase,
+            values: self.values,
+            told: self.told,
+            seed: self.seed,
+            draws: self.draws,
 
-        let y1 = target.evaluate(op!(mut r4, x, dx => x + dx));
-        let y2 = target.evaluate(op!(mut r4, x, dx => x - dx));
+            warmup: self.warmup,
+            m1: self.m1,
+            m2: self.m2,
+            b1: self.b1,
+            b2: self.b2,
+            bn: self.bn,
+            y: self.y,
+            noise: self.noise,
+            lr: self.lr,
+            mx: self.mx,
+            bx: self.bx,
+            y_best: self.y_best,
+            y3: self.y3,
+            y6: self.y6,
+            step: self.step,
+            dxx: self.dxx,
+            value: self.value,
 
-        let df = nz((y1 - y) * 0.5) - nz((y2 - y) * 0.5);
-        let df_dx = op!(mut dx => df / dx);
+            hessian: self.hessian.clone(),
+            tdx: self.tdx.clone(),
+            hn: self.hn,
+            curvature: self.curvature,
+            y1: self.y1,
+            y2: self.y2,
+            gradient_sum: self.gradient_sum.clone(),
 
-        b1 += m1 * (1.0 - b1);
-        b2 += m2 * (1.0 - b2);
+            momentum_fails: self.momentum_fails,
+            consecutive_fails: self.consecutive_fails,
+            improvement_fails: self.improvement_fails,
 
-        op!(mut gx, df_dx => gx + m1 * (df_dx - gx));
-        op!(mut slow_gx, df_dx => slow_gx + m2 * (df_dx - slow_gx));
-        op!(mut square_gx, slow_gx => square_gx + m2 * ((slow_gx / b2) * (slow_gx / b2) - square_gx));
+            i: self.i,
+            evaluations: self.evaluations,
+            stop: self.stop
+        }
+    }
+}
+
+impl<R: RngCore> SpsaState<R> {
+
+    /// Begin SPSA optimization starting at `point`, drawing perturbations from a given random number generator.
+    /// [Seed](Options#structfield.seed) option is ignored.
+    /// Returns a [SpsaError] if the options are invalid.
+    pub fn with_rng(point: &[f64], options: Options, rng: R) -> Result<Self, SpsaError> {
+        Self::with_registers(point, options, rng, &mut Default::default())
+    }
+
+    /// Continue the optimization from a [Checkpoint].
+    ///
+    /// `options` should be the same as the ones the optimization has been started with,
+    /// although [stopping criteria](Options#structfield.stop) and the [iteration limit](Options#structfield.iterations)
+    /// may be changed freely.
+    pub fn resume(checkpoint: Checkpoint<R>, options: Options) -> Self {
+        Self::resume_with_registers(checkpoint, options, Default::default())
+    }
+
+    pub(crate) fn resume_with_registers(checkpoint: Checkpoint<R>, options: Options, registers: Registers) -> Self {
+        let Registers { mut diag, mut scratch, .. } = registers;
+        let Checkpoint {
+            r, rng,
+            phase, values, told, seed, draws,
+            warmup, m1, m2, b1, b2, bn, y, noise, lr, mx, bx, y_best, y3, y6, step, dxx, value,
+            hessian, tdx, hn, curvature, y1, y2, gradient_sum,
+            momentum_fails, consecutive_fails, improvement_fails,
+            i, evaluations, stop
+        } = checkpoint;
+
+        reuse(&mut diag, tdx.len());
+        reuse(&mut scratch, 2 * hessian.len() + tdx.len());
+
+        Self {
+            options, r, rng,
+            phase, values, told, seed, draws,
+            warmup, m1, m2, b1, b2, bn, y, noise, lr, mx, bx, y_best, y3, y6, step, dxx, value,
+            diag, scratch,
+            hessian, tdx, hn, curvature, y1, y2, gradient_sum,
+            momentum_fails, consecutive_fails, improvement_fails,
+            i, evaluations, stop
+        }
+    }
+
+    /// Begins the optimization in the given registers, which are left untouched if the options are invalid
+    pub(crate) fn with_registers(point: &[f64], options: Options, rng: R, registers: &mut Registers) -> Result<Self, SpsaError> {
+        validate(&options, point.len())?;
+
+        let Registers { mut r, mut hessian, mut scratch, mut tdx, mut diag, mut gradient_sum } = std::mem::take(registers);
+
+        for v in &mut r {
+            reuse(v, point.len());
+        }
+
+        r[0].copy_from_slice(point);
+        project(&options, &mut r[0]);
+
+        let n = match options.method {
+            Method::FirstOrder => 0,
+            Method::SecondOrder => point.len()
+        };
+
+        let probes = if probes_curvature(&options) { point.len() } else { 0 };
+
+        reuse(&mut hessian, n * n);
+        reuse(&mut scratch, 2 * n * n + n);
+        reuse(&mut tdx, probes);
+        reuse(&mut diag, probes);
+        reuse(&mut gradient_sum, if options.gradient_samples > 1 { point.len() } else { 0 });
+
+        Ok(Self {
+            warmup: f64::sqrt(point.len() as f64 + 100.0) as usize,
+            m1: 1.0 - options.momentum,
+            m2: 1.0 - options.beta,
+            b1: 0.0,
+            b2: 0.0,
+            bn: 0.0,
+            y: 0.0,
+            noise: 0.0,
+            lr: 0.0,
+            mx: 0.0,
+            bx: 0.0,
+            y_best: 0.0,
+            y3: 0.0,
+            y6: 0.0,
+            step: 0.0,
+            dxx: 0.0,
+            value: 0.0,
+
+            hessian,
+            scratch,
+            tdx,
+            diag,
+            hn: 0,
+            curvature: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+            gradient_sum,
+
+            momentum_fails: 0,
+            consecutive_fails: 0,
+            improvement_fails: 0,
+
+            i: 0,
+            evaluations: 0,
+            stop: StopReason::Iterations,
+
+            phase: Phase::WarmUp(0),
+            values: [0.0; BATCH_MAX],
+            told: 0,
+            seed: 0,
+            draws: 0,
+
+            rng,
+            options,
+            r
+        })
     }
 
-    let mut lr = match lr {
-        Some(lr) => lr,
-        None => {
-            let mut lr = 1e-5;
-            let dx = op!(mut r3, gx => 3.0 / b1 * gx);
+    pub(crate) fn into_registers(self) -> Registers {
+        Registers {
+            r: self.r,
+            hessian: self.hessian,
+            scratch: self.scratch,
+            tdx: self.tdx,
+            diag: self.diag,
+            gradient_sum: self.gradient_sum
+        }
+    }
+
+    /// Whether a checkpoint is due according to [Options::checkpoint_interval]
+    fn checkpoint_due(&self) -> bool {
+        match self.options.checkpoint_interval {
+            Some(interval) => interval > 0 && self.i.is_multiple_of(interval),
+            None => false
+        }
+    }
+
+    /// Returns the next point to evaluate, or `None` if the optimization has finished.
+    ///
+    /// If an iteration has just finished, it is [continued](Control::Continue).
+    pub fn ask(&mut self) -> Option<&[f64]> {
+        self.control(Control::Continue);
+        self.round_x();
+
+        let query = *self.phase.queries(&self.options).get(self.told)?;
+        Some(self.query(query))
+    }
+
+    /// Returns all the points that can be evaluated independently of each other,
+    /// or nothing if the optimization has finished.
+    ///
+    /// Their values are to be fed in the same order via [tell_batch](SpsaState::tell_batch()).
+    pub fn ask_batch(&mut self) -> impl ExactSizeIterator<Item = &[f64]> + '_ {
+        self.control(Control::Continue);
+        self.round_x();
+        let state = &*self;
+
+        state.phase.queries(&state.options)[state.told..]
+            .iter()
+            .map(move |query| state.query(*query))
+    }
+
+    /// Returns the random seed of the point returned by the last [ask](SpsaState::ask()),
+    /// or of the points returned by the last [ask_batch](SpsaState::ask_batch()).
+    ///
+    /// Both sides of a perturbation, as well as their second-order probes and both line search probes, share the same seed,
+    /// see [Target::evaluate_seeded()]. Other points should be evaluated with independent noise.
+    pub fn seed(&self) -> Option<u64> {
+        match self.phase {
+            Phase::Gradient(_) | Phase::Perturb(_) | Phase::Curvature(_) | Phase::LineSearch => Some(self.seed),
+            _ => None
+        }
+    }
+
+    /// Returns the [Role] of the point returned by the last [ask](SpsaState::ask()),
+    /// or of the point the next one will return if called before it.
+    pub fn role(&self) -> Option<Role> {
+        let query = *self.phase.queries(&self.options).get(self.told)?;
+
+        Some(match (self.phase, query) {
+            (Phase::WarmUp(_), _) | (Phase::Gradient(_), _) | (Phase::LrSearch(_), _) => Role::WarmUp,
+            (Phase::Perturb(_), Query::P1) => Role::PerturbationPlus,
+            (Phase::Perturb(_), _) => Role::PerturbationMinus,
+            (Phase::Curvature(_), _) => Role::Curvature,
+            (Phase::LineSearch, _) => Role::LineSearch,
+            _ => Role::Accept
+        })
+    }
+
+    /// Feed the function value at the point returned by the last [ask](SpsaState::ask()).
+    /// Return `f64::NAN` if the point is out of bounds.
+    ///
+    /// # Panics
+    /// If there is no point waiting to be evaluated
+    pub fn tell(&mut self, value: f64) -> Result<(), SpsaError> {
+        let queries = self.phase.queries(&self.options);
+        assert!(self.told < queries.len(), "no point is waiting to be evaluated");
+
+        self.values[self.told] = value * self.options.direction.sign();
+        self.told += 1;
+        self.evaluations += 1;
+
+        if self.told < queries.len() {
+            return Ok(());
+        }
+
+        self.told = 0;
+
+        if self.options.deterministic { // repeated evaluations would have given the same values
+            let [v0, v1, ..] = self.values;
+
+            match self.phase {
+                Phase::LrSearch(_) => self.values = [v0, v0, v1, v1],
+                Phase::WarmUp(_) | Phase::Start | Phase::Step | Phase::Rollback | Phase::Finish => self.values[1] = v0,
+                _ => {}
+            }
+        }
+
+        let result = self.advance();
+
+        if result.is_err() {
+            self.phase = Phase::Failed;
+        }
+
+        result
+    }
+
+    /// Feed the function values at the points returned by the last [ask_batch](SpsaState::ask_batch()).
+    ///
+    /// # Panics
+    /// If there are more values than points waiting to be evaluated
+    pub fn tell_batch(&mut self, values: &[f64]) -> Result<(), SpsaError> {
+        for value in values {
+            self.tell(*value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the iteration data if an iteration has just finished.
+    ///
+    /// The point, the gradient and the learning rate may be modified before the optimization proceeds.
+    pub fn iteration(&mut self) -> Option<Iteration<'_>> {
+        if let Phase::Iterated = self.phase {
+            let [x, .., average, gradient] = &mut self.r;
+            let sign = self.options.direction.sign();
+
+            Some(Iteration {
+                iteration: self.i,
+                point: x,
+                gradient,
+                learning_rate: &mut self.lr,
+                evaluations: self.evaluations,
+                value: sign * self.y / self.bn,
+                best: sign * self.y_best,
+                noise: f64::sqrt(self.noise / self.bn),
+                momentum: 1.0 - self.m1,
+                perturbation: self.dxx,
+                average,
+                consecutive_fails: self.consecutive_fails,
+                improvement_fails: self.improvement_fails
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Decide how to proceed after an iteration has finished, see [Target::iteration()]
+    ///
+    /// Does nothing if no iteration has just finished.
+    pub fn control(&mut self, control: Control) {
+        if !matches!(self.phase, Phase::Iterated) {
+            return;
+        }
+
+        let [_, gx, .., gradient] = &mut self.r;
+        let sign = self.options.direction.sign();
+        op!(mut gx, gradient => gradient * sign);
+
+        self.i += 1;
+
+        if control == Control::Stop {
+            self.finish(StopReason::Interrupted);
+            return;
+        }
+
+        if let Some(criterion) = &mut self.options.stop {
+            let status = Status {
+                iteration: self.i,
+                evaluations: self.evaluations,
+                direction: self.options.direction,
+                value: sign * self.y / self.bn,
+                best: sign * self.y_best,
+                step: self.step,
+                learning_rate: self.lr
+            };
 
-            if adam {
-                op!(mut dx, square_gx => dx * (square_gx / b2 + epsilon).rsqrte());
+            if let Some(reason) = criterion.check(&status) {
+                self.finish(reason);
+                return;
             }
+        }
+
+        if control == Control::Restart || self.consecutive_fails >= 128 * (self.improvement_fails + self.warmup) {
+            self.restart();
+        }
+
+        // one-sided perturbations are compared against the value at the current point,
+        // which has to be evaluated again if a restart or the target has moved it
+        if self.options.one_sided && self.r[0] != self.r[7] {
+            self.phase = Phase::Start;
+            return;
+        }
+
+        self.begin_iteration();
+    }
+
+    /// Current point; once the optimization has finished, the optimized point
+    pub fn point(&self) -> &[f64] {
+        &self.r[0]
+    }
+
+    /// Returns the [Report] once the optimization has successfully finished
+    pub fn report(&self) -> Option<Report> {
+        if let Phase::Done = self.phase {
+            Some(Report {
+                value: self.options.direction.sign() * self.value,
+                iterations: self.i,
+                evaluations: self.evaluations,
+                learning_rate: self.lr,
+                restarts: self.improvement_fails,
+                stop: self.stop,
+                hessian: match self.options.method {
+                    Method::FirstOrder => None,
+                    Method::SecondOrder => Some(self.hessian.iter().map(|h| h * self.options.direction.sign()).collect())
+                }
+            })
+        } else {
+            None
+        }
+    }
+
+    fn query(&self, query: Query) -> &[f64] {
+        match query {
+            Query::X if self.options.kinds.is_some() => &self.r[9],
+            Query::X => &self.r[0],
+            Query::P1 => &self.r[8],
+            Query::P2 => &self.r[9]
+        }
+    }
+
+    fn advance(&mut self) -> Result<(), SpsaError> {
+        let [v0, v1, v2, v3] = self.values;
+
+        match self.phase {
+            Phase::WarmUp(k) => {
+                let m2 = self.m2;
+
+                self.bn += m2 * (1.0 - self.bn);
+                self.y += m2 * (v0 - self.y);
+                self.noise += m2 * (f64::powi(v0 - v1, 2) - self.noise);
+
+                if k + 1 < self.warmup && !self.options.deterministic {
+                    self.phase = Phase::WarmUp(k + 1);
+                } else if self.y.is_nan() { // initial point cannot be nan
+                    return Err(SpsaError::InfeasibleStart);
+                } else {
+                    self.begin_gradient(0);
+                }
+            },
+            Phase::Gradient(k) => {
+                let [_, gx, slow_gx, square_gx, _, _, _, dx, ..] = &mut self.r;
+                let (m1, m2, y) = (self.m1, self.m2, self.y);
+
+                let df = nz((v0 - y) * 0.5) - nz((v1 - y) * 0.5);
+                let df_dx = match self.options.perturbation {
+                    Perturbation::Gaussian => { // perturbations may be arbitrarily close to zero
+                        let df = df * f64::powi(1.0 + k as f64, 2);
+                        op!(mut dx => dx * df)
+                    },
+                    _ => op!(mut dx => df / dx)
+                };
 
-            for _ in 0..5 {
-                let ls = op!(mut r4, dx, x => x - lr * dx);
+                self.b1 += m1 * (1.0 - self.b1);
+                self.b2 += m2 * (1.0 - self.b2);
 
-                let a = f64::max(target.evaluate(ls), target.evaluate(ls));
-                let b = f64::max(target.evaluate(x), target.evaluate(x));
+                op!(mut gx, df_dx => gx + m1 * (df_dx - gx));
+                op!(mut slow_gx, df_dx => slow_gx + m2 * (df_dx - slow_gx));
+
+                if let Some(preconditioner) = &self.options.preconditioner {
+                    preconditioner.update(square_gx, &Moments {
+                        gradient: df_dx,
+                        momentum: gx,
+                        momentum_bias: self.b1,
+                        slow: slow_gx,
+                        curvature: None,
+                        weight: m2,
+                        bias: self.b2
+                    });
+                }
+
+                if k + 1 < self.warmup {
+                    self.begin_gradient(k + 1);
+                } else {
+                    self.begin_lr_search();
+                }
+            },
+            Phase::LrSearch(k) => {
+                let a = f64::max(v0, v1);
+                let b = f64::max(v2, v3);
 
                 if a > b {
-                    lr *= 1.4;
+                    self.lr *= 1.4;
+
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(lr = self.lr, "learning rate search increased the learning rate");
+
+                    self.search(k + 1);
+                } else {
+                    self.begin_main();
+                }
+            },
+            Phase::Start => {
+                self.y3 = v0;
+                self.y6 = v1;
+                self.begin_iteration();
+            },
+            Phase::Perturb(k) => {
+                // one-sided perturbations are compared against the current point
+                let (y1, y2) = if self.options.one_sided { (v0, self.y3) } else { (v0, v1) };
+
+                if probes_curvature(&self.options) {
+                    self.begin_curvature(k, y1, y2);
                 } else {
-                    break;
+                    self.perturbed(k, y1, y2, false)?;
+                }
+            },
+            Phase::Curvature(k) => {
+                let curved = self.estimate_curvature(v0, v1);
+                self.perturbed(k, self.y1, self.y2, curved)?;
+            },
+            Phase::LineSearch => self.line_searched(Some((v0, v1))),
+            Phase::Step => {
+                if !self.options.line_search { // without probes, the learning rate follows the outcome of the step
+                    let noise_factor = f64::sqrt(self.noise / self.bn);
+                    self.lr *= if v0 + 0.25 * noise_factor > self.y3 { 1.1 } else { 1.0 / 1.3 };
+
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(iteration = self.i, lr = self.lr, improved = v0 + 0.25 * noise_factor > self.y3, "learning rate tuned by the step");
+                }
+
+                self.y3 = v0;
+                self.y6 = v1;
+
+                if !self.y3.is_finite() || !self.y6.is_finite() {
+                    let [x, .., prev, _] = &mut self.r;
+                    op!(mut x, prev => prev);
+
+                    self.consecutive_fails += 10;
+                    self.phase = Phase::Rollback;
+
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(iteration = self.i, "step went out of bounds, rolling back");
+                } else {
+                    self.end_iteration();
+                }
+            },
+            Phase::Rollback => {
+                self.y3 = v0;
+                self.y6 = v1;
+
+                if !self.y3.is_finite() || !self.y6.is_finite() {
+                    let [x, _, _, _, _, x_best, ..] = &mut self.r;
+                    x.copy_from_slice(x_best);
+
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(iteration = self.i, "rolled back point is out of bounds");
+
+                    return Err(SpsaError::StuckOutOfBounds { iteration: self.i });
+                }
+
+                self.end_iteration();
+            },
+            Phase::Finish => {
+                let [x, _, _, _, _, x_best, ..] = &mut self.r;
+                self.value = f64::max(v0, v1);
+
+                if self.y_best + 0.25 * f64::sqrt(self.noise / self.bn) > self.value || self.value.is_nan() {
+                    x.copy_from_slice(x_best);
+                    self.value = self.y_best;
                 }
+
+                snap(&self.options, x);
+                self.phase = Phase::Done;
+            },
+            Phase::Iterated | Phase::Done | Phase::Failed => unreachable!()
+        }
+
+        Ok(())
+    }
+
+    fn begin_gradient(&mut self, k: usize) {
+        let [x, .., dx, p1, p2] = &mut self.r;
+
+        perturb(self.options.perturbation, &mut self.rng, self.draws, dx);
+        self.draws += 1;
+
+        op!(mut dx => dx / (1.0 + k as f64));
+        op!(mut p1, x, dx => x + dx);
+        op!(mut p2, x, dx => x - dx);
+
+        lattice(&self.options.kinds, p1, p2, dx);
+        snap(&self.options, p1);
+        snap(&self.options, p2);
+
+        self.seed = self.rng.next_u64();
+        self.phase = Phase::Gradient(k);
+    }
+
+    fn begin_lr_search(&mut self) {
+        match self.options.lr {
+            Some(lr) => {
+                self.lr = lr;
+                self.begin_main();
+            },
+            None => {
+                let [_, gx, _, square_gx, _, _, dx, ..] = &mut self.r;
+                let b1 = self.b1;
+
+                op!(mut dx, gx => 3.0 / b1 * gx);
+                precondition(&self.options, square_gx, self.b2, dx);
+
+                self.lr = 1e-5;
+                self.search(0);
             }
+        }
+    }
 
-            lr
+    fn search(&mut self, k: usize) {
+        if k >= 5 {
+            self.begin_main();
+            return;
         }
-    };
 
-    let mx = f64::sqrt(m1 * m2);
-    let mut bx = mx;
+        let [x, _, _, _, _, _, dx, _, ls, _] = &mut self.r;
+        let lr = self.lr;
+
+        op!(mut ls, dx, x => x - lr * dx);
+        snap(&self.options, ls);
+
+        self.phase = Phase::LrSearch(k);
+    }
+
+    fn begin_main(&mut self) {
+        let [x, gx, _, square_gx, x_avg, x_best, dx, ..] = &mut self.r;
+        let b1 = self.b1;
+
+        let mx = f64::sqrt(self.m1 * self.m2);
+        self.mx = mx;
+        self.bx = mx;
 
-    let x_avg = op!(mut r3, x => mx * x);
+        op!(mut x_avg, x => mx * x);
 
-    let mut y_best = y / bn;
-    let x_best = op!(mut r4, x => x);
+        self.y_best = self.y / self.bn;
+        op!(mut x_best, x => x);
 
-    let mut momentum_fails = 0;
-    let mut consecutive_fails = 0;
-    let mut improvement_fails = 0;
+        op!(mut dx, gx => gx / b1);
+        precondition(&self.options, square_gx, self.b2, dx);
 
-    let dx = op!(mut r5, gx => gx / b1);
+        self.phase = Phase::Start;
+    }
+
+    fn begin_iteration(&mut self) {
+        if self.i >= self.options.iterations {
+            self.finish(StopReason::Iterations);
+            return;
+        }
 
-    if adam {
-        op!(mut dx, square_gx => dx * (square_gx / b2 + epsilon).rsqrte());
+        project(&self.options, &mut self.r[0]);
+        self.begin_perturb(0);
     }
 
-    let mut y3 = target.evaluate(x);
-    let mut y6 = target.evaluate(x);
+    /// Perturbs the next point for the `k`-th gradient sample of the iteration
+    fn begin_perturb(&mut self, k: usize) {
+        let newton = self.newton();
+        let [x, _, _, square_gx, _, _, dx, ndx, x_next, p2] = &mut self.r;
+        let Options { px, px_decay, px_power, .. } = self.options;
+        let (lr, i) = (self.lr, self.i);
+
+        // two-sided perturbations look ahead along the step, one-sided ones stay at the current point
+        let (ahead, back) = if self.options.one_sided { (0.0, 1.0) } else { (lr, 2.0) };
+        let px = if self.options.one_sided { px * ONE_SIDED_PX } else { px };
+        op!(mut x_next, dx, x => x + ahead * dx);
+
+        let dxx = (lr / self.m1 * px / f64::powf(1.0 + px_decay * i as f64, px_power)) * norm(dx);
+        perturb(self.options.perturbation, &mut self.rng, self.draws, ndx);
+        self.draws += 1;
+        self.dxx = dxx;
+
+        op!(mut ndx => ndx * dxx);
+
+        if !newton {
+            precondition(&self.options, square_gx, self.b2, ndx);
+        }
+
+        op!(mut x_next, ndx => x_next + ndx);
+        op!(mut p2, x_next, ndx => x_next - back * ndx);
+
+        lattice(&self.options.kinds, x_next, p2, ndx);
+        snap(&self.options, x_next);
+        snap(&self.options, p2);
+
+        self.seed = self.rng.next_u64();
+        self.phase = Phase::Perturb(k);
+    }
+
+    /// Probes both sides of the perturbation once more, shifted by another perturbation (2SPSA)
+    fn begin_curvature(&mut self, k: usize, y1: f64, y2: f64) {
+        let [.., ndx, p1, p2] = &mut self.r;
+        let tdx = &mut self.tdx;
+
+        self.y1 = y1;
+        self.y2 = y2;
+
+        let perturbation = match self.options.perturbation {
+            Perturbation::Hadamard => Perturbation::Rademacher, // would be correlated with the first perturbation
+            perturbation => perturbation
+        };
+
+        perturb(perturbation, &mut self.rng, 0, tdx);
+        op!(mut tdx, ndx => tdx * ndx);
+        op!(mut p1, tdx => p1 + tdx);
+        op!(mut p2, tdx => p2 + tdx);
+
+        snap(&self.options, p1);
+        snap(&self.options, p2);
+
+        self.phase = Phase::Curvature(k);
+    }
+
+    /// Estimates the curvature from the differences between the gradients at both sides of the perturbation
+    /// along the second perturbation, updating the running Hessian estimate if there is one.
+    /// Returns whether the diagonal of the Hessian sample could be estimated.
+    fn estimate_curvature(&mut self, y1: f64, y2: f64) -> bool {
+        let ndx = &self.r[7];
+        let (tdx, diag) = (&self.tdx, &mut self.diag);
+        let n = ndx.len();
+
+        let mut d = (y1 - self.y1) - (y2 - self.y2);
+
+        if self.options.one_sided { // both sides are one perturbation apart instead of two
+            d *= 2.0;
+        }
+
+        if !d.is_finite() {
+            return false;
+        }
+
+        // once the perturbation is too small to tell curvature from rounding errors, keep the estimates as they are;
+        // the difference is predicted from the curvature so far, since filtering by `d` itself would bias them
+        let roundoff = f64::EPSILON * (y1.abs() + y2.abs() + self.y1.abs() + self.y2.abs());
+
+        if self.curvature > 0.0 && 2.0 * self.curvature * norm2(ndx) < 1e4 * roundoff {
+            return false;
+        }
+
+        let w = f64::max(1.0 / (self.hn + 1) as f64, self.m2);
 
-    for i in 0..iterations {
-        let x_next = op!(mut r6, dx, x => x + lr * dx);
+        for i in 0..n {
+            diag[i] = nz(0.5 * d / (tdx[i] * ndx[i]));
+        }
+
+        let largest = diag.iter().fold(0.0, |a, h| f64::max(a, h.abs()));
+        self.curvature += w * (largest - self.curvature);
 
-        let dxx = (lr / m1 * px / f64::powf(1.0 + px_decay * i as f64, px_power)) * norm(dx);
-        let ndx = op!(mut r7 => randsign(&mut rng) * dxx);
+        if !self.hessian.is_empty() {
+            for i in 0..n {
+                for j in 0..n {
+                    let h = 0.25 * d * (1.0 / (tdx[i] * ndx[j]) + 1.0 / (tdx[j] * ndx[i]));
+                    let hessian = &mut self.hessian[i * n + j];
 
-        if adam {
-            op!(mut ndx, square_gx => ndx * (square_gx / b2 + epsilon).rsqrte());
+                    if h.is_finite() {
+                        *hessian += w * (h - *hessian);
+                    }
+                }
+            }
         }
 
-        let y1 = target.evaluate(op!(mut x_next, ndx => x_next + ndx));
-        let y2 = target.evaluate(op!(mut x_next, ndx => x_next - 2.0 * ndx));
-        let df = (nz((y1 - y) * 0.5) - nz((y2 - y) * 0.5)) * f64::sqrt(size as f64) / norm2(ndx);
+        self.hn += 1;
+        true
+    }
+
+    /// Whether steps are taken along the Newton direction of the Hessian estimate
+    fn newton(&self) -> bool {
+        !self.hessian.is_empty() && self.hn >= self.warmup
+    }
+
+    fn perturbed(&mut self, k: usize, y1: f64, y2: f64, curved: bool) -> Result<(), SpsaError> {
+        let newton = self.newton();
+        let [x, gx, slow_gx, square_gx, _, x_best, dx, ndx, p1, p2] = &mut self.r;
+        let Options { momentum, lr_decay, lr_power, .. } = self.options;
+        let (y, m2, lr, i) = (self.y, self.m2, self.lr, self.i);
+        let size = x.len();
+
+        let half = if self.options.one_sided { 1.0 } else { 0.5 };
+        let dy = nz((y1 - y) * half) - nz((y2 - y) * half);
+        let df = dy * f64::sqrt(size as f64) / norm2(ndx);
 
         if !df.is_finite() {
-            break;
+            if dy.is_finite() { // perturbation has vanished, nothing left to gain
+                self.finish(StopReason::Converged);
+                return Ok(());
+            }
+
+            x.copy_from_slice(x_best);
+            return Err(SpsaError::NonFiniteGradient { iteration: i });
         }
 
         let df_dx = op!(mut ndx => ndx * df);
+        let q = self.options.gradient_samples;
+
+        if q > 1 { // average the gradient samples of the iteration
+            let gradient_sum = &mut self.gradient_sum;
+
+            if k == 0 {
+                gradient_sum.copy_from_slice(df_dx);
+            } else {
+                op!(mut gradient_sum, df_dx => gradient_sum + df_dx);
+            }
+
+            if k + 1 < q {
+                self.begin_perturb(k + 1);
+                return Ok(());
+            }
 
-        if cosine(df_dx, gx) < 0.5 / f64::powf(1.0 + 0.1 * momentum_fails as f64, 0.3) - 1.0 {
-            momentum_fails += 1;
-            m1 = (1.0 - momentum) / f64::sqrt(1.0 + 0.1 * momentum_fails as f64);
+            let qf = q as f64;
+            op!(mut df_dx, gradient_sum => gradient_sum / qf);
         }
 
-        b1 += m1 * (1.0 - b1);
-        b2 += m2 * (1.0 - b2);
+        if cosine(df_dx, gx) < 0.5 / f64::powf(1.0 + 0.1 * self.momentum_fails as f64, 0.3) - 1.0 {
+            self.momentum_fails += 1;
+            self.m1 = (1.0 - momentum) / f64::sqrt(1.0 + 0.1 * self.momentum_fails as f64);
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(iteration = self.i, momentum_fails = self.momentum_fails, momentum = 1.0 - self.m1, "gradient disagrees with momentum, momentum increased");
+        }
+
+        let m1 = self.m1;
+        self.b1 += m1 * (1.0 - self.b1);
+        self.b2 += m2 * (1.0 - self.b2);
+        let (b1, b2) = (self.b1, self.b2);
 
         op!(mut gx, df_dx => gx + m1 * (df_dx - gx));
         op!(mut slow_gx, df_dx => slow_gx + m2 * (df_dx - slow_gx));
-        op!(mut square_gx, slow_gx => square_gx + m2 * ((slow_gx / b2) * (slow_gx / b2) - square_gx));
+
+        if let Some(preconditioner) = &self.options.preconditioner {
+            preconditioner.update(square_gx, &Moments {
+                gradient: df_dx,
+                momentum: gx,
+                momentum_bias: b1,
+                slow: slow_gx,
+                curvature: Some(&self.diag[..]).filter(|_| curved),
+                weight: m2,
+                bias: b2
+            });
+        }
 
         let fa = 1.0 / (b1 * f64::powf(1.0 + lr_decay * i as f64, lr_power));
         op!(mut dx, gx => gx * fa);
 
-        if adam {
-            op!(mut dx, square_gx => dx * (square_gx / b2 + epsilon).rsqrte());
+        if newton {
+            newton_step(&self.hessian, &mut self.scratch, dx);
+        } else {
+            precondition(&self.options, square_gx, b2, dx);
+        }
+
+        if !self.options.line_search {
+            self.line_searched(None);
+            return Ok(());
         }
 
         let m1s = f64::sqrt(m1);
-        let y4 = target.evaluate(op!(mut r6, x, dx => x + lr * 0.5 * dx));
-        let y5 = target.evaluate(op!(mut r6, x, dx => x + lr / m1s * dx));
+        op!(mut p1, x, dx => x + lr * 0.5 * dx);
+        op!(mut p2, x, dx => x + lr / m1s * dx);
 
-        bn += m2 * (1.0 - bn);
-        y += m2 * (y3 - y);
-        noise += m2 * (f64::powi(y3 - y6, 2) + 1e-64 * (y3.abs() + y6.abs()) - noise);
+        snap(&self.options, p1);
+        snap(&self.options, p2);
 
-        let noise_factor = f64::sqrt(noise / bn);
+        self.seed = self.rng.next_u64();
+        self.phase = Phase::LineSearch;
+        Ok(())
+    }
 
-        if y3 + 0.25 * noise_factor > f64::max(y4, y5) {
-            lr /= 1.3;
-        }
+    /// Tunes the learning rate from the values of the line search probes, if there are any, then takes the step
+    fn line_searched(&mut self, probes: Option<(f64, f64)>) {
+        let [x, .., dx, _, prev, _] = &mut self.r;
+        let (y3, y6, m2, i) = (self.y3, self.y6, self.m2, self.i);
+        let epsilon = self.options.epsilon;
+
+        self.bn += m2 * (1.0 - self.bn);
+        self.y += m2 * (y3 - self.y);
+        self.noise += m2 * (f64::powi(y3 - y6, 2) + 1e-64 * (y3.abs() + y6.abs()) - self.noise);
+
+        if let Some((y4, y5)) = probes {
+            let noise_factor = f64::sqrt(self.noise / self.bn);
+
+            if y3 + 0.25 * noise_factor > f64::max(y4, y5) {
+                self.lr /= 1.3;
 
-        if y4 + 0.25 * noise_factor > f64::max(y3, y5) {
-            lr *= 1.3 / 1.4;
+                #[cfg(feature = "tracing")]
+                tracing::debug!(iteration = i, lr = self.lr, "current point is best, learning rate decreased");
+            }
+
+            if y4 + 0.25 * noise_factor > f64::max(y3, y5) {
+                self.lr *= 1.3 / 1.4;
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(iteration = i, lr = self.lr, "shorter probe is best, learning rate decreased");
+            }
+
+            if y5 + 0.25 * noise_factor > f64::max(y3, y4) {
+                self.lr *= 1.4;
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(iteration = i, lr = self.lr, "longer probe is best, learning rate increased");
+            }
         }
 
-        if y5 + 0.25 * noise_factor > f64::max(y3, y4) {
-            lr *= 1.4;
+        self.lr = f64::max(self.lr, epsilon / f64::sqrt(1.0 + 0.01 * i as f64) * (1.0 + 0.25 * norm(x)));
+
+        // once the learning rate has grown to make up for a vanishing gradient, a gradient estimate gone wrong
+        // would blow the point up; one-sided estimates and steps tuned without probes can't tell, so they grow slowest
+        if self.step > 0.0 {
+            let growth = if self.options.one_sided || !self.options.line_search { STEP_GROWTH } else { PROBED_STEP_GROWTH };
+            let limit = growth * self.step;
+            let length = norm(dx);
+
+            if length * self.lr > limit {
+                self.lr = limit / length;
+            }
         }
 
-        lr = f64::max(lr, epsilon / f64::sqrt(1.0 + 0.01 * i as f64) * (1.0 + 0.25 * norm(&x)));
+        let lr = self.lr;
 
-        let prev = op!(mut r6, x => x);
+        self.step = norm(dx) * lr;
+        op!(mut prev, x => x);
         op!(mut x, dx => x + dx * lr);
+        step_lattice(&self.options, i, x, prev, dx);
+        project(&self.options, x);
 
-        y3 = target.evaluate(x);
-        y6 = target.evaluate(x);
-
-        if !y3.is_finite() || !y6.is_finite() {
-            op!(mut x, prev => prev);
+        self.phase = Phase::Step;
+    }
 
-            y3 = target.evaluate(x);
-            y6 = target.evaluate(x);
+    fn end_iteration(&mut self) {
+        let [x, _, _, _, x_avg, x_best, ..] = &mut self.r;
 
-            consecutive_fails += 10;
+        let fa = self.mx / f64::powf(1.0 + 0.01 * self.i as f64, 0.303);
 
-            if !y3.is_finite() || !y6.is_finite() {
-                panic!("stuck out of bounds");
-            }
+        // points that have blown up, whose squared norm overflows, never make it into the average
+        if norm2(x).is_finite() {
+            self.bx += fa * (1.0 - self.bx);
+            op!(mut x_avg, x => x_avg + fa * (x - x_avg));
         }
 
-        let fa = mx / f64::powf(1.0 + 0.01 * i as f64, 0.303);
-        bx += fa * (1.0 - bx);
-        op!(mut x_avg, x => x_avg + fa * (x - x_avg));
+        self.consecutive_fails += 1;
 
-        consecutive_fails += 1;
+        if self.y / self.bn > self.y_best && norm2(x_avg).is_finite() {
+            let bx = self.bx;
 
-        if y / bn > y_best {
-            y_best = y / bn;
+            self.y_best = self.y / self.bn;
             op!(mut x_best, x_avg => x_avg / bx);
-            consecutive_fails = 0;
+            self.consecutive_fails = 0;
         }
 
-        target.iteration(Iteration {
-            iteration: i,
-            point: x,
-            gradient: gx,
-            learning_rate: &mut lr
-        });
+        // gradient and average are exposed in the free registers until the iteration is controlled,
+        // along with the evaluated point to tell whether it has been moved since
+        let [x, gx, _, _, x_avg, _, _, evaluated, average, gradient] = &mut self.r;
+        let (sign, bx) = (self.options.direction.sign(), self.bx);
+        op!(mut gradient, gx => gx * sign);
+        op!(mut average, x_avg => x_avg / bx);
+        evaluated.copy_from_slice(x);
 
-        if consecutive_fails < 128 * (improvement_fails + (f64::sqrt(size as f64 + 100.0) as i32)) {
-            continue;
-        }
+        self.phase = Phase::Iterated;
+    }
+
+    fn restart(&mut self) {
+        let [x, gx, slow_gx, square_gx, x_avg, x_best, ..] = &mut self.r;
+        let (mx, m1, m2) = (self.mx, self.m1, self.m2);
 
-        consecutive_fails = 0;
-        improvement_fails += 1;
+        self.consecutive_fails = 0;
+        self.improvement_fails += 1;
 
         x.copy_from_slice(x_best);
-        bx = mx * (1.0 - mx);
+        let bx = mx * (1.0 - mx);
+        self.bx = bx;
         op!(mut x_avg, x => x * bx);
 
-        noise *= m2 * (1.0 - m2) / bn;
-        y = m2 * (1.0 - m2) * y_best;
-        bn = m2 * (1.0 - m2);
-        b1 = m1 * (1.0 - m1);
+        self.noise *= m2 * (1.0 - m2) / self.bn;
+        self.y = m2 * (1.0 - m2) * self.y_best;
+        self.bn = m2 * (1.0 - m2);
+        self.b1 = m1 * (1.0 - m1);
 
+        let (b1, b2) = (self.b1, self.b2);
         let fa = m2 * (1.0 - m2) / b2;
         op!(mut gx, slow_gx => b1 / b2 * slow_gx);
         op!(mut slow_gx => slow_gx * fa);
-        op!(mut square_gx => square_gx * fa);
 
-        b2 = m2 * (1.0 - m2);
-        lr /= 64.0 * improvement_fails as f64;
+        if let Some(preconditioner) = &self.options.preconditioner {
+            preconditioner.restart(square_gx, fa);
+        }
+
+        self.b2 = m2 * (1.0 - m2);
+        self.lr /= 64.0 * self.improvement_fails as f64;
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(iteration = self.i, restarts = self.improvement_fails, best = self.options.direction.sign() * self.y_best, lr = self.lr, "restarted from the best point");
     }
 
-    if y_best + 0.25 * f64::sqrt(noise / bn) > f64::max(target.evaluate(x), target.evaluate(x)) {
-        x.copy_from_slice(x_best);
+    /// Rounds the current point for evaluation if there are [discrete dimensions](Options#structfield.kinds).
+    ///
+    /// The rounded point is kept in the last register, which is free whenever the current point is queried.
+    fn round_x(&mut self) {
+        if self.options.kinds.is_some() && self.phase.queries(&self.options).iter().any(|q| matches!(q, Query::X)) {
+            let [x, .., rounded] = &mut self.r;
+            rounded.copy_from_slice(x);
+            snap(&self.options, rounded);
+        }
+    }
+
+    fn finish(&mut self, reason: StopReason) {
+        self.stop = reason;
+        self.phase = Phase::Finish;
+    }
+
+    /// Stops with [StopReason::Evaluations] if `left` evaluations may not cover another iteration
+    /// along with the final evaluations, see [Target::evaluations_left()].
+    ///
+    /// If they may not even cover the current phase, stops at once without evaluating any further points.
+    pub(crate) fn limit(&mut self, left: usize) {
+        let cost = |phase: Phase| phase.queries(&self.options).len();
+
+        let iteration = match self.phase {
+            Phase::Start if self.told == 0 => cost(Phase::Start) + self.iteration_cost(),
+            Phase::Perturb(0) if self.told == 0 => self.iteration_cost(),
+            _ => 0
+        };
+
+        if iteration > 0 && left < iteration + cost(Phase::Finish) {
+            self.finish(StopReason::Evaluations);
+        }
+
+        if left < self.phase.queries(&self.options).len() - self.told {
+            self.exhaust();
+        }
+    }
+
+    /// Evaluations taken by an iteration that does not roll back
+    fn iteration_cost(&self) -> usize {
+        let cost = |phase: Phase| phase.queries(&self.options).len();
+        let curvature = if probes_curvature(&self.options) { cost(Phase::Curvature(0)) } else { 0 };
+        let line_search = if self.options.line_search { cost(Phase::LineSearch) } else { 0 };
+
+        self.options.gradient_samples * (cost(Phase::Perturb(0)) + curvature) + line_search + cost(Phase::Step)
+    }
+
+    /// Stops with [StopReason::Evaluations] at the best point known so far, or at the initial point during warm-up
+    fn exhaust(&mut self) {
+        let [x, _, _, _, _, x_best, ..] = &mut self.r;
+
+        match self.phase {
+            Phase::WarmUp(_) | Phase::Gradient(_) | Phase::LrSearch(_) => self.value = self.y / self.bn,
+            _ => {
+                x.copy_from_slice(x_best);
+                self.value = self.y_best;
+            }
+        }
+
+        snap(&self.options, x);
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(iteration = self.i, "evaluations ran out");
+
+        self.stop = StopReason::Evaluations;
+        self.told = 0;
+        self.phase = Phase::Done;
+    }
+}
+
+/// Checks that the options fit an argument vector of a given length
+fn validate(options: &Options, len: usize) -> Result<(), SpsaError> {
+    if let Some(bounds) = &options.bounds {
+        if bounds.len() != len || bounds.iter().any(|&(lo, hi)| lo.is_nan() || hi.is_nan() || lo > hi) {
+            return Err(SpsaError::InvalidBounds);
+        }
+    }
+
+    if let Some(kinds) = &options.kinds {
+        if kinds.len() != len || (options.one_sided && kinds.iter().any(|k| k.is_discrete())) {
+            return Err(SpsaError::InvalidKinds);
+        }
+
+        if let Some(bounds) = &options.bounds { // discrete dimensions need a valid value within their bounds
+            for (kind, &(lo, hi)) in kinds.iter().zip(bounds) {
+                let (lo, hi) = match *kind {
+                    ParamKind::Continuous => continue,
+                    ParamKind::Integer => (lo.ceil(), hi.floor()),
+                    ParamKind::Categorical(n) => (lo.ceil().max(0.0), hi.floor().min(n as f64 - 1.0))
+                };
+
+                if lo > hi {
+                    return Err(SpsaError::InvalidBounds);
+                }
+            }
+        }
+    }
+
+    if options.gradient_samples == 0 {
+        return Err(SpsaError::NoGradientSamples);
+    }
+
+    let valid = match options.perturbation {
+        Perturbation::SegmentedUniform(min, max) => min > 0.0 && min <= max && max.is_finite(),
+        Perturbation::Bernoulli(magnitude) => magnitude > 0.0 && magnitude.is_finite(),
+        _ => true
+    };
+
+    if !valid {
+        return Err(SpsaError::InvalidPerturbation);
+    }
+
+    Ok(())
+}
+
+/// Zeroes a buffer and resizes it to `len`, keeping its allocation
+fn reuse(v: &mut Vec<f64>, len: usize) {
+    v.clear();
+    v.resize(len, 0.0);
+}
+
+/// Projects a point into the [bounds](Options#structfield.bounds), if there are any
+fn project(options: &Options, point: &mut [f64]) {
+    if let Some(bounds) = &options.bounds {
+        clamp(point, bounds);
+    }
+}
+
+/// Projects a point into the bounds, then rounds its [discrete dimensions](Options#structfield.kinds)
+/// to the nearest lattice point within the bounds
+fn snap(options: &Options, point: &mut [f64]) {
+    project(options, point);
+
+    if let Some(kinds) = &options.kinds {
+        for (k, (v, kind)) in point.iter_mut().zip(kinds).enumerate() {
+            *v = kind.round(*v);
+
+            if let (true, Some(bounds)) = (kind.is_discrete(), &options.bounds) {
+                let (lo, hi) = bounds[k];
+                *v = v.clamp(lo.ceil(), hi.floor()); // rounding may have left the bounds
+            }
+        }
+    }
+}
+
+/// Steps discrete dimensions with their own decaying gain, at most one lattice point per iteration.
+///
+/// Function values don't change between lattice points, so the learning rate tuned by the line search
+/// only fits continuous dimensions and would eventually freeze discrete ones.
+fn step_lattice(options: &Options, i: usize, x: &mut [f64], prev: &[f64], dx: &[f64]) {
+    let kinds = match &options.kinds {
+        Some(kinds) => kinds,
+        None => return
+    };
+
+    let gain = 0.5 / f64::powf(1.0 + options.lr_decay * i as f64, options.lr_power);
+
+    for k in 0..kinds.len() {
+        if kinds[k].is_discrete() {
+            x[k] = prev[k] + (gain * dx[k]).clamp(-1.0, 1.0);
+        }
+
+        if let ParamKind::Categorical(n) = kinds[k] {
+            x[k] = x[k].clamp(0.0, n.saturating_sub(1) as f64);
+        }
+    }
+}
+
+/// Scales a step or a perturbation with the [preconditioner](Options#structfield.preconditioner), if there is one
+fn precondition(options: &Options, stats: &[f64], bias: f64, v: &mut [f64]) {
+    if let Some(preconditioner) = &options.preconditioner {
+        preconditioner.apply(stats, bias, options.epsilon, v);
+    }
+}
+
+/// Whether the optimization makes two more evaluations per iteration to estimate the curvature
+fn probes_curvature(options: &Options) -> bool {
+    let diagonal = match &options.preconditioner {
+        Some(preconditioner) => preconditioner.curvature(),
+        None => false
+    };
+
+    diagonal || options.method == Method::SecondOrder
+}
+
+/// Turns a gradient step into a Newton step.
+///
+/// The Hessian estimate is conditioned to be positive definite by taking absolute values of its eigenvalues
+/// and keeping them above a small fraction of the largest one, so the step never heads towards a saddle
+/// or blows up along a direction of little curvature.
+fn newton_step(hessian: &[f64], scratch: &mut [f64], dx: &mut [f64]) {
+    let n = dx.len();
+    let (a, scratch) = scratch.split_at_mut(n * n);
+    let (v, t) = scratch.split_at_mut(n * n);
+
+    a.copy_from_slice(hessian);
+    jacobi(a, v, n);
+
+    let max = (0..n).map(|i| a[i * n + i].abs()).fold(0.0, f64::max);
+    let floor = 1e-3 * max + 1e-64;
+
+    for i in 0..n {
+        let projected: f64 = (0..n).map(|k| v[k * n + i] * dx[k]).sum();
+        t[i] = projected / f64::max(a[i * n + i].abs(), floor);
+    }
+
+    for k in 0..n {
+        dx[k] = (0..n).map(|i| v[k * n + i] * t[i]).sum();
+    }
+}
+
+/// Moves both sides of a perturbation of discrete dimensions onto neighbouring lattice points,
+/// so that they never round to the same value (discrete SPSA).
+/// The half-difference between the sides, `delta`, is updated accordingly.
+fn lattice(kinds: &Option<Vec<ParamKind>>, p1: &mut [f64], p2: &mut [f64], delta: &mut [f64]) {
+    let kinds = match kinds {
+        Some(kinds) => kinds,
+        None => return
+    };
+
+    for i in 0..kinds.len() {
+        if !kinds[i].is_discrete() {
+            continue;
+        }
+
+        let sign = if p1[i] >= p2[i] { 0.5 } else { -0.5 };
+        let mut center = f64::floor(0.5 * (p1[i] + p2[i])) + 0.5;
+
+        if let ParamKind::Categorical(n) = kinds[i] {
+            center = center.min(n as f64 - 1.5).max(0.5);
+        }
+
+        p1[i] = center + sign;
+        p2[i] = center - sign;
+        delta[i] = sign;
+    }
+}
+
+/// Stage of the optimization traced by its own span
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    WarmUp,
+    LrSearch,
+    Main(usize)
+}
+
+/// Spans of the stages of the optimization, the main loop gets a child span after every restart
+#[cfg(feature = "tracing")]
+struct Spans {
+    stage: Option<Stage>,
+    main: tracing::Span,
+    span: tracing::Span
+}
+
+#[cfg(feature = "tracing")]
+impl Spans {
+    fn new() -> Self {
+        Self {
+            stage: None,
+            main: tracing::Span::none(),
+            span: tracing::Span::none()
+        }
+    }
+
+    /// Enters the span of the stage the state is at, opening a new one whenever the stage changes
+    fn enter<R: RngCore>(&mut self, state: &SpsaState<R>) -> tracing::span::Entered<'_> {
+        let stage = match state.phase {
+            Phase::WarmUp(_) | Phase::Gradient(_) => Stage::WarmUp,
+            Phase::LrSearch(_) => Stage::LrSearch,
+            _ => Stage::Main(state.improvement_fails)
+        };
+
+        if self.stage != Some(stage) {
+            self.span = match stage {
+                Stage::WarmUp => tracing::info_span!("warm_up"),
+                Stage::LrSearch => tracing::info_span!("lr_search"),
+                Stage::Main(0) => {
+                    self.main = tracing::info_span!("main");
+                    self.main.clone()
+                },
+                Stage::Main(restart) => tracing::info_span!(parent: &self.main, "restart", restart)
+            };
+
+            self.stage = Some(stage);
+        }
+
+        self.span.enter()
+    }
+}
+
+pub type Snapshot<R> = fn(&SpsaState<R>) -> Option<Checkpoint>;
+
+pub fn optimize<T: Target, R: RngCore>(mut target: T, state: &mut SpsaState<R>, snapshot: Snapshot<R>) -> Result<Report, SpsaError> {
+    state.options.deterministic |= target.is_deterministic();
+
+    #[cfg(feature = "tracing")]
+    let mut spans = Spans::new();
+
+    loop {
+        #[cfg(feature = "tracing")]
+        let _span = spans.enter(state);
+
+        if let Some(left) = target.evaluations_left() {
+            state.limit(left);
+        }
+
+        let seed = state.seed();
+
+        if let Some(role) = state.role() {
+            target.evaluating(role);
+        }
+
+        let value = match (state.ask(), seed) {
+            (Some(point), Some(seed)) => target.evaluate_seeded(point, seed),
+            (Some(point), None) => target.evaluate(point),
+            (None, _) => break
+        };
+
+        state.tell(value)?;
+
+        if let Some(iter) = state.iteration() {
+            let control = target.iteration(iter);
+            state.control(control);
+
+            if state.checkpoint_due() {
+                if let Some(checkpoint) = snapshot(state) {
+                    target.checkpoint(checkpoint);
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+
+    state.report().ok_or(SpsaError::Unfinished)
+}
+
+pub fn optimize_batch<T: BatchTarget, R: RngCore>(mut target: T, state: &mut SpsaState<R>, snapshot: Snapshot<R>) -> Result<Report, SpsaError> {
+    let mut values = [0.0; BATCH_MAX];
+    state.options.deterministic |= target.is_deterministic();
+
+    #[cfg(feature = "tracing")]
+    let mut spans = Spans::new();
+
+    loop {
+        #[cfg(feature = "tracing")]
+        let _span = spans.enter(state);
+
+        if let Some(left) = target.evaluations_left() {
+            state.limit(left);
+        }
+
+        let seed = state.seed();
+        let mut points: [&[f64]; BATCH_MAX] = [&[]; BATCH_MAX];
+        let mut n = 0;
+
+        for point in state.ask_batch() {
+            points[n] = point;
+            n += 1;
+        }
+
+        if n == 0 {
+            break;
+        }
+
+        match seed {
+            Some(seed) => target.evaluate_batch_seeded(&points[..n], &mut values[..n], seed),
+            None => target.evaluate_batch(&points[..n], &mut values[..n])
+        }
+
+        state.tell_batch(&values[..n])?;
+
+        if let Some(iter) = state.iteration() {
+            let control = target.iteration(iter);
+            state.control(control);
+
+            if state.checkpoint_due() {
+                if let Some(checkpoint) = snapshot(state) {
+                    target.checkpoint(checkpoint);
+                }
+            }
+        }
+    }
+
+    state.report().ok_or(SpsaError::Unfinished)
+}
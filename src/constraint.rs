@@ -0,0 +1,288 @@
+use crate::{Control, Iteration, Optimizer, Options, Report, Role, SpsaError, Target};
+
+/// Represents a function to optimize subject to constraints
+///
+/// Constraints are expected to be cheap relative to the objective function.
+pub trait ConstrainedTarget {
+
+    /// Evaluate the objective function (to optimize in the [direction](Options#structfield.direction) of the options) at a given point
+    /// Return `f64::NAN` if we're out of bounds
+    fn objective(&mut self, data: &[f64]) -> f64;
+
+    /// Evaluate the objective function at a given point using a given random seed,
+    /// see [Target::evaluate_seeded()]
+    #[allow(unused_variables)]
+    fn objective_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.objective(data)
+    }
+
+    /// Number of inequality constraints `g_i(x) <= 0`
+    fn inequalities(&self) -> usize {
+        0
+    }
+
+    /// Number of equality constraints `h_j(x) = 0`
+    fn equalities(&self) -> usize {
+        0
+    }
+
+    /// Evaluate the constraints at a given point, storing `g_i(x)` in `g` and `h_j(x)` in `h`
+    fn constraints(&mut self, data: &[f64], g: &mut [f64], h: &mut [f64]);
+
+    /// Called after every optimizer iteration of every inner optimization
+    /// Returned [Control] value decides how the inner optimization proceeds
+    #[allow(unused_variables)]
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        Control::Continue
+    }
+
+    /// Whether the objective and the constraints always return the same values at the same point,
+    /// see [Options::deterministic](crate::Options#structfield.deterministic)
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+
+    /// Called before every evaluation of every inner optimization with the [Role] of the point about to be evaluated
+    #[allow(unused_variables)]
+    fn evaluating(&mut self, role: Role) {}
+}
+
+impl<'a, T: ConstrainedTarget> ConstrainedTarget for &'a mut T {
+    #[inline]
+    fn objective(&mut self, data: &[f64]) -> f64 {
+        (*self).objective(data)
+    }
+
+    #[inline]
+    fn objective_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        (*self).objective_seeded(data, seed)
+    }
+
+    #[inline]
+    fn inequalities(&self) -> usize {
+        (**self).inequalities()
+    }
+
+    #[inline]
+    fn equalities(&self) -> usize {
+        (**self).equalities()
+    }
+
+    #[inline]
+    fn constraints(&mut self, data: &[f64], g: &mut [f64], h: &mut [f64]) {
+        (*self).constraints(data, g, h)
+    }
+
+    #[inline]
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        (*self).iteration(iter)
+    }
+
+    #[inline]
+    fn is_deterministic(&self) -> bool {
+        (**self).is_deterministic()
+    }
+
+    #[inline]
+    fn evaluating(&mut self, role: Role) {
+        (*self).evaluating(role)
+    }
+}
+
+/// Options of the augmented Lagrangian method used in [Optimizer::optimize_constrained()]
+///
+/// Each outer iteration runs a whole SPSA optimization of the augmented Lagrangian,
+/// then updates the Lagrange multipliers and, if the constraint violation
+/// has not decreased enough, increases the penalty.
+pub struct AugmentedLagrangian {
+    /// Maximum number of outer iterations
+    pub iterations: usize,
+
+    /// Initial penalty
+    pub penalty: f64,
+
+    /// Factor the penalty is multiplied by when the constraint violation has not decreased enough
+    pub penalty_growth: f64,
+
+    /// Penalty is never increased beyond this value
+    pub max_penalty: f64,
+
+    /// Maximum constraint violation allowed to stop early
+    pub tolerance: f64
+}
+
+impl Default for AugmentedLagrangian {
+    fn default() -> Self {
+        Self {
+            iterations: 10,
+            penalty: 10.0,
+            penalty_growth: 10.0,
+            max_penalty: 1e8,
+            tolerance: 1e-6
+        }
+    }
+}
+
+/// Summary of a constrained optimization process returned by [Optimizer::optimize_constrained()]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct ConstrainedReport {
+    /// Report of the last inner optimization
+    pub report: Report,
+
+    /// Objective function value at the optimized point
+    pub objective: f64,
+
+    /// Values of inequality constraints `g_i(x)` at the optimized point
+    pub inequalities: Vec<f64>,
+
+    /// Values of equality constraints `h_j(x)` at the optimized point
+    pub equalities: Vec<f64>,
+
+    /// Largest constraint violation at the optimized point
+    pub violation: f64,
+
+    /// Number of outer iterations actually run
+    pub iterations: usize,
+
+    /// Total number of objective function evaluations made
+    pub evaluations: usize
+}
+
+/// Augmented Lagrangian of a [ConstrainedTarget], minimized by the inner optimizations
+struct Lagrangian<'a, T> {
+    source: &'a mut T,
+    g: Vec<f64>,
+    h: Vec<f64>,
+    mu: Vec<f64>,
+    lambda: Vec<f64>,
+    penalty: f64,
+    sign: f64
+}
+
+impl<'a, T: ConstrainedTarget> Lagrangian<'a, T> {
+
+    /// Evaluates the constraints, returning the largest violation
+    fn violation(&mut self, data: &[f64]) -> f64 {
+        self.source.constraints(data, &mut self.g, &mut self.h);
+
+        let g = self.g.iter().map(|g| g.max(0.0));
+        let h = self.h.iter().map(|h| h.abs());
+
+        g.chain(h).fold(0.0, f64::max)
+    }
+
+    /// Evaluates the constraints and adds their penalties to the objective function value `f`
+    fn penalize(&mut self, data: &[f64], f: f64) -> f64 {
+        self.source.constraints(data, &mut self.g, &mut self.h);
+
+        let penalty = self.penalty;
+        let mut l = -self.sign * f;
+
+        for (mu, g) in self.mu.iter().zip(&self.g) {
+            l += (f64::powi(f64::max(0.0, mu + penalty * g), 2) - mu * mu) / (2.0 * penalty);
+        }
+
+        for (lambda, h) in self.lambda.iter().zip(&self.h) {
+            l += lambda * h + 0.5 * penalty * h * h;
+        }
+
+        -self.sign * l // values are told in the direction of the options
+    }
+
+    fn update_multipliers(&mut self) {
+        let penalty = self.penalty;
+
+        for (mu, g) in self.mu.iter_mut().zip(&self.g) {
+            *mu = f64::max(0.0, *mu + penalty * g);
+        }
+
+        for (lambda, h) in self.lambda.iter_mut().zip(&self.h) {
+            *lambda += penalty * h;
+        }
+    }
+}
+
+impl<'a, T: ConstrainedTarget> Target for Lagrangian<'a, T> {
+    fn evaluate(&mut self, data: &[f64]) -> f64 {
+        let f = self.source.objective(data);
+        self.penalize(data, f)
+    }
+
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        let f = self.source.objective_seeded(data, seed);
+        self.penalize(data, f)
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.source.is_deterministic()
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
+    }
+}
+
+pub fn optimize<T: ConstrainedTarget>(
+    optimizer: &mut Optimizer,
+    mut target: T,
+    vector: &mut [f64],
+    mut options: impl FnMut() -> Options,
+    lagrangian: AugmentedLagrangian
+) -> Result<ConstrainedReport, SpsaError> {
+    let ni = target.inequalities();
+    let ne = target.equalities();
+
+    let mut inner = Lagrangian {
+        source: &mut target,
+        g: vec![0.0; ni],
+        h: vec![0.0; ne],
+        mu: vec![0.0; ni],
+        lambda: vec![0.0; ne],
+        penalty: lagrangian.penalty,
+        sign: 1.0
+    };
+
+    let mut violation = inner.violation(vector);
+    let mut evaluations = 0;
+    let mut iterations = 0;
+    let mut report;
+
+    loop {
+        let options = options();
+        inner.sign = options.direction.sign();
+
+        report = optimizer.optimize(&mut inner, vector, options)?;
+        evaluations += report.evaluations;
+        iterations += 1;
+
+        let prev = violation;
+        violation = inner.violation(vector);
+
+        if violation <= lagrangian.tolerance || iterations >= lagrangian.iterations {
+            break;
+        }
+
+        inner.update_multipliers();
+
+        if violation > 0.25 * prev {
+            inner.penalty = f64::min(inner.penalty * lagrangian.penalty_growth, lagrangian.max_penalty);
+        }
+    }
+
+    let Lagrangian { g, h, .. } = inner;
+
+    Ok(ConstrainedReport {
+        report,
+        objective: target.objective(vector),
+        inequalities: g,
+        equalities: h,
+        violation,
+        iterations,
+        evaluations: evaluations + 1
+    })
+}
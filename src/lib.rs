@@ -2,17 +2,43 @@ mod utils;
 mod vec;
 mod algo;
 mod target;
+mod stop;
+mod constraint;
+mod space;
+mod precondition;
 
 pub use target::*;
+pub use stop::*;
+pub use precondition::*;
+pub use constraint::{AugmentedLagrangian, ConstrainedReport, ConstrainedTarget};
+pub use algo::{Checkpoint, SpsaState};
+pub use space::{ParamSpace, Params};
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use rand::RngCore;
 
 /// Optimization options used in [Optimizer::optimize()]
 pub struct Options {
-    /// Use adaptive moment estimation
-    pub adam: bool,
+    /// Whether the target function is maximized or minimized
+    ///
+    /// Function values and gradients reported by [Report], [Iteration] and [Status] follow this direction.
+    pub direction: Direction,
+
+    /// Whether to estimate the Hessian in addition to the gradient, see [Method]
+    pub method: Method,
+
+    /// Scaling of steps and perturbations of every dimension (set to `None` to not scale them)
+    ///
+    /// Defaults to [Adam], adaptive moment estimation.
+    pub preconditioner: Option<Box<dyn Preconditioner>>,
 
     /// Maximum number of iterations
     pub iterations: usize,
 
+    /// Additional [stopping criterion](StopCriterion), checked after every iteration
+    pub stop: Option<Box<dyn StopCriterion>>,
+
     /// Learning rate (set to `None` to use estimated value)
     /// The learning rate controls the speed of convergence
     /// ```rust
@@ -45,47 +71,417 @@ pub struct Options {
     /// [Perturbation size](#structfield.px) power
     pub px_power: f64,
 
+    /// Distribution of the [perturbation](#structfield.px) of every dimension, see [Perturbation]
+    pub perturbation: Perturbation,
+
+    /// Number of perturbation pairs evaluated per iteration
+    ///
+    /// Their gradient estimates are averaged, which reduces the variance of the gradient on noisy functions
+    /// at the cost of two (or four with second-order probes) evaluations per sample.
+    /// Unlike [Oversample], which averages function values, every sample perturbs in another direction.
+    pub gradient_samples: usize,
+
+    /// Whether to estimate the gradient from one side of the perturbation only
+    ///
+    /// The perturbed point is compared against the value at the current point, which is already known
+    /// unless [Target::iteration()] has moved it, saving one evaluation per gradient sample. Curvature biases one-sided estimates, so perturbations are
+    /// much smaller than the [perturbation size](#structfield.px) would make them; best suited to
+    /// [deterministic](#structfield.deterministic) functions. Not supported with [discrete dimensions](#structfield.kinds).
+    /// Steps may at most double from one iteration to the next.
+    pub one_sided: bool,
+
+    /// Whether to evaluate two probes along every step to tune the [learning rate](#structfield.lr)
+    ///
+    /// Without them, the learning rate is tuned by whether every step has improved the function value,
+    /// saving two evaluations per iteration, and steps may at most double from one iteration to the next.
+    /// With them, steps of [two-sided](#structfield.one_sided) gradient estimates may still grow at most 16-fold.
+    pub line_search: bool,
+
+    /// Whether the target function is free of noise (also set if [Target::is_deterministic()] says so)
+    ///
+    /// Points are then evaluated once where they would otherwise be evaluated twice to estimate the noise,
+    /// and the warm-up takes a single evaluation.
+    pub deterministic: bool,
+
     /// The momentum controls how much of the gradient is kept from previous iterations.
     /// Automatically tunes itself to increase as necessary.
     pub momentum: f64,
 
     /// A secondary momentum, which should be much closer to 1 than the [other momentum](#structfield.momentum).
-    /// This is used by the [Adam](#structfield.adam) method.
+    /// This is used by the [preconditioner](#structfield.preconditioner).
     pub beta: f64,
 
-    /// Used to avoid division by 0 in the [Adam](#structfield.adam) method.
-    pub epsilon: f64
+    /// Used to avoid division by 0 in the [preconditioner](#structfield.preconditioner).
+    pub epsilon: f64,
+
+    /// Seed of the random number generator used for perturbations (set to `None` to seed from entropy)
+    ///
+    /// Identical seeds, options and starting points produce bit-identical results on deterministic functions.
+    pub seed: Option<u64>,
+
+    /// Number of iterations between calls to [Target::checkpoint()] (set to `None` to never checkpoint)
+    ///
+    /// Every checkpoint is a copy of the optimizer state, the only allocation made during the optimization process.
+    pub checkpoint_interval: Option<usize>,
+
+    /// Lower and upper bound of every dimension (set to `None` for an unconstrained problem)
+    ///
+    /// Every point is projected into these bounds before evaluation,
+    /// so simple parameter ranges never cost a failed evaluation, unlike returning `f64::NAN`.
+    /// Must have the same length as the argument vector, and no lower bound may exceed its upper bound.
+    pub bounds: Option<Vec<(f64, f64)>>,
+
+    /// Kind of every dimension (set to `None` if all of them are continuous)
+    ///
+    /// Discrete dimensions are optimized on a continuous relaxation: every point is rounded
+    /// to the nearest valid value within the [bounds](#structfield.bounds) before evaluation,
+    /// and they are perturbed by ±1 around the lattice.
+    /// [Target::iteration()] sees the relaxed point, while the optimized point is rounded.
+    /// Must have the same length as the argument vector.
+    pub kinds: Option<Vec<ParamKind>>
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
-            adam: true,
+            direction: Direction::Maximize,
+            method: Method::FirstOrder,
+            preconditioner: Some(Box::new(Adam)),
             iterations: 10_000,
+            stop: None,
             lr: None,
             lr_decay: 1e-3,
             lr_power: 0.5,
             px: 2.0,
             px_decay: 1e-2,
             px_power: 0.161,
+            perturbation: Perturbation::Rademacher,
+            gradient_samples: 1,
+            one_sided: false,
+            line_search: true,
+            deterministic: false,
             momentum: 0.9,
             beta: 0.999,
-            epsilon: 1e-7
+            epsilon: 1e-7,
+            seed: None,
+            checkpoint_interval: None,
+            bounds: None,
+            kinds: None
+        }
+    }
+}
+
+/// Direction of the optimization, see [Options::direction]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Find inputs that maximize the function output
+    Maximize,
+
+    /// Find inputs that minimize the function output
+    Minimize
+}
+
+impl Direction {
+    /// `1` when maximizing, `-1` when minimizing.
+    /// Multiplying a function value by the sign turns it into a value to maximize.
+    pub fn sign(self) -> f64 {
+        match self {
+            Direction::Maximize => 1.0,
+            Direction::Minimize => -1.0
+        }
+    }
+}
+
+/// Optimization method, see [Options::method]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Estimate the gradient only, scaling steps with the [preconditioner](Options#structfield.preconditioner)
+    FirstOrder,
+
+    /// Spall's second-order SPSA (2SPSA)
+    ///
+    /// Two more evaluations per iteration maintain a running estimate of the Hessian,
+    /// which is conditioned to be positive definite and used for Newton-like steps once warmed up,
+    /// replacing the [preconditioner](Options#structfield.preconditioner) from then on.
+    /// Converges much faster on badly scaled problems, at the cost of `O(n²)` memory and `O(n³)` time per iteration.
+    SecondOrder
+}
+
+/// Distribution of perturbations, see [Options::perturbation]
+///
+/// Perturbations are scaled by the [perturbation size](Options#structfield.px) after being drawn,
+/// so distributions with unit magnitude keep its meaning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Perturbation {
+    /// Random signs, ±1 with equal probability
+    Rademacher,
+
+    /// Random signs times a magnitude drawn uniformly between the given minimum and maximum
+    ///
+    /// The minimum must be positive, since the gradient is estimated by dividing by the perturbation during warm-up,
+    /// and no greater than the maximum, otherwise [SpsaError::InvalidPerturbation] is returned.
+    SegmentedUniform(f64, f64),
+
+    /// Random signs times a given magnitude, which must be positive and finite
+    Bernoulli(f64),
+
+    /// Standard normal samples, as used by Gaussian smoothing variants of SPSA
+    ///
+    /// Not suited for [Method::SecondOrder], since Hessian samples are divided by the perturbation.
+    Gaussian,
+
+    /// Rows of a Hadamard matrix, cycled through deterministically
+    ///
+    /// Over a full cycle, perturbations of different dimensions are exactly uncorrelated,
+    /// which reduces the variance of gradient estimates compared to random signs.
+    /// Cycles are as long as the smallest power of two greater than the number of dimensions.
+    /// Hessian samples of [Method::SecondOrder] still use random signs for their second perturbation.
+    Hadamard
+}
+
+/// Kind of a single dimension of the argument vector, see [Options::kinds]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    /// Any real value
+    Continuous,
+
+    /// Whole numbers only
+    Integer,
+
+    /// One of `n` categories, numbered `0..n`
+    ///
+    /// Neighbouring categories are assumed to be similar, since they are perturbed into each other.
+    Categorical(usize)
+}
+
+impl ParamKind {
+    /// Whether the dimension only takes values on the integer lattice
+    pub(crate) fn is_discrete(self) -> bool {
+        !matches!(self, ParamKind::Continuous)
+    }
+
+    /// Nearest valid value of the dimension
+    pub(crate) fn round(self, v: f64) -> f64 {
+        match self {
+            ParamKind::Continuous => v,
+            ParamKind::Integer => v.round(),
+            ParamKind::Categorical(n) => v.round().max(0.0).min(n.saturating_sub(1) as f64)
         }
     }
 }
 
 /// The heart of this library: a simultaneous perturbation stochastic approximation optimizer
 #[derive(Default, Clone)]
-pub struct Optimizer([Vec<f64>; algo::REGISTER_NUM]);
+pub struct Optimizer(algo::Registers);
+
+/// Reason why the optimization process has stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum StopReason {
+    /// Maximum number of [iterations](Options#structfield.iterations) was reached
+    Iterations,
+
+    /// Perturbation size has vanished, so no further progress could be made
+    Converged,
+
+    /// Evaluation budget was exhausted, see [MaxEvaluations] and [Budget]
+    Evaluations,
+
+    /// Deadline was reached, see [Deadline]
+    Deadline,
+
+    /// Target function value was reached, see [TargetValue]
+    TargetValue,
+
+    /// Function value has stopped improving, see [NoImprovement]
+    NoImprovement,
+
+    /// Step size became too small, see [StepSize]
+    StepSize,
+
+    /// [Target::iteration()] has returned [Control::Stop]
+    Interrupted,
+
+    /// Stopped by a user-defined [StopCriterion]
+    Custom
+}
+
+/// Errors that may occur during the optimization process
+///
+/// Unless stated otherwise, the argument vector holds the best point found so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SpsaError {
+    /// Function evaluated to NaN at the initial point.
+    /// The argument vector holds the initial point, projected into the [bounds](Options#structfield.bounds).
+    InfeasibleStart,
+
+    /// Both the new point and the previous point evaluated to non-finite values
+    StuckOutOfBounds {
+        iteration: usize
+    },
+
+    /// Function evaluated to an infinite value, so the gradient could not be estimated
+    NonFiniteGradient {
+        iteration: usize
+    },
+
+    /// The optimization ended without finishing, e.g. it was resumed from a state that had already failed
+    Unfinished,
+
+    /// Parameters of the [perturbation distribution](Options#structfield.perturbation) are out of range.
+    /// The argument vector is left untouched.
+    InvalidPerturbation,
+
+    /// [Bounds](Options#structfield.bounds) are not given for every dimension,
+    /// some lower bound is above its upper bound or NaN, or the bounds of a discrete dimension hold no valid value.
+    /// The argument vector is left untouched.
+    InvalidBounds,
+
+    /// [Kinds](Options#structfield.kinds) are not given for every dimension,
+    /// discrete dimensions are combined with [one-sided](Options#structfield.one_sided) perturbations,
+    /// or kinds are given to [Optimizer::optimize_space()].
+    /// The argument vector is left untouched.
+    InvalidKinds,
+
+    /// [Gradient samples](Options#structfield.gradient_samples) are set to zero.
+    /// The argument vector is left untouched.
+    NoGradientSamples
+}
+
+impl Display for SpsaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SpsaError::InfeasibleStart => write!(f, "function is not defined at the initial point"),
+            SpsaError::StuckOutOfBounds { iteration } => write!(f, "stuck out of bounds at iteration {}", iteration),
+            SpsaError::NonFiniteGradient { iteration } => write!(f, "non-finite gradient estimate at iteration {}", iteration),
+            SpsaError::Unfinished => write!(f, "optimization ended without finishing"),
+            SpsaError::InvalidPerturbation => write!(f, "perturbation distribution parameters are out of range"),
+            SpsaError::InvalidBounds => write!(f, "bounds are not given for every dimension, or some of them are inverted, NaN or hold no valid value"),
+            SpsaError::InvalidKinds => write!(f, "kinds are not given for every dimension, or are not supported along with other options"),
+            SpsaError::NoGradientSamples => write!(f, "at least one gradient sample must be taken per iteration")
+        }
+    }
+}
+
+impl Error for SpsaError {}
+
+/// Summary of an optimization process returned by [Optimizer::optimize()]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Report {
+    /// Best function value found
+    pub value: f64,
+
+    /// Number of iterations actually run
+    pub iterations: usize,
+
+    /// Number of [Target::evaluate()] calls made
+    pub evaluations: usize,
+
+    /// Learning rate at the end of the optimization
+    pub learning_rate: f64,
+
+    /// Number of restarts from the best point found so far
+    pub restarts: usize,
+
+    /// Why the optimization has stopped
+    pub stop: StopReason,
+
+    /// Hessian estimate at the end of the optimization, in row-major order,
+    /// if [Method::SecondOrder] has been used
+    ///
+    /// Follows the [direction](Options#structfield.direction) of the optimization;
+    /// when minimizing a negative log-likelihood, its inverse approximates the covariance of the estimate.
+    pub hessian: Option<Vec<f64>>
+}
 
 /// Optimizer cycle iteration data
+///
+/// Function values and the gradient follow the [direction](Options#structfield.direction) of the optimization.
 #[non_exhaustive]
 pub struct Iteration<'a> {
     pub iteration: usize,
     pub point: &'a mut [f64],
     pub gradient: &'a mut [f64],
     pub learning_rate: &'a mut f64,
+
+    /// Number of evaluations made so far
+    pub evaluations: usize,
+
+    /// Current smoothed function value
+    pub value: f64,
+
+    /// Best smoothed function value found so far
+    pub best: f64,
+
+    /// Estimated standard deviation of the difference between two evaluations of the same point
+    pub noise: f64,
+
+    /// Current momentum, lowered whenever gradient estimates disagree with it
+    pub momentum: f64,
+
+    /// Size of every dimension of the last perturbation, before the [preconditioner](Options#structfield.preconditioner) scales it
+    pub perturbation: f64,
+
+    /// Running average of the visited points, which the best point is taken from
+    pub average: &'a [f64],
+
+    /// Number of iterations since the best value last improved; the optimization restarts once it grows too large
+    pub consecutive_fails: usize,
+
+    /// Number of restarts so far
+    pub improvement_fails: usize
+}
+
+/// Tells the optimizer how to proceed after an iteration, see [Target::iteration()]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Carry on with the optimization
+    Continue,
+
+    /// Stop the optimization; the best point found so far is still selected as usual
+    Stop,
+
+    /// Restart the optimization from the best point found so far
+    Restart
+}
+
+/// Purpose of an evaluated point, see [Target::evaluating()]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Role {
+    /// Noise estimation, initial gradient estimates and the initial learning rate search
+    WarmUp,
+
+    /// Positive side of a perturbation
+    PerturbationPlus,
+
+    /// Negative side of a perturbation
+    PerturbationMinus,
+
+    /// Second-order probe of a perturbation, see [Method::SecondOrder]
+    Curvature,
+
+    /// Probe along the step, used to tune the learning rate
+    LineSearch,
+
+    /// Current point, after a step has been accepted or the optimization has finished
+    Accept
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::WarmUp => "warm-up",
+            Role::PerturbationPlus => "perturbation+",
+            Role::PerturbationMinus => "perturbation-",
+            Role::Curvature => "curvature",
+            Role::LineSearch => "line-search",
+            Role::Accept => "accept"
+        })
+    }
 }
 
 impl Optimizer {
@@ -97,8 +493,11 @@ impl Optimizer {
 
     /// Begin SPSA optimizing function `target` starting at `vector`.
     /// Optimized argument vector will be stored in the `vector` argument.
+    /// Returns a [Report] describing the optimization process,
+    /// or a [SpsaError] if the optimization could not proceed.
     ///
-    /// An optimization process is a process of finding such inputs that maximize an output of some function.
+    /// An optimization process is a process of finding such inputs that maximize an output of some function,
+    /// or minimize it if the [direction](Options#structfield.direction) says so.
     ///
     /// # Example
     /// ```rust
@@ -116,19 +515,165 @@ impl Optimizer {
     ///  let mut optimizer = Optimizer::new();
     ///  let mut input = [0.0, 0.0];
     ///
-    ///  optimizer.optimize(SimpleFunction, &mut input, Options::default());
+    ///  optimizer.optimize(SimpleFunction, &mut input, Options::default()).unwrap();
     ///
     ///  assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
     ///  assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
     /// ```
-    pub fn optimize<T: Target>(&mut self, target: T, vector: &mut [f64], options: Options) {
-        let size = vector.len();
+    pub fn optimize<T: Target>(&mut self, target: T, vector: &mut [f64], options: Options) -> Result<Report, SpsaError> {
+        let rng = utils::seeded(options.seed);
+        let mut state = SpsaState::with_registers(vector, options, rng, &mut self.0)?;
+        let result = algo::optimize(target, &mut state, |state| Some(state.checkpoint()));
 
-        for v in &mut self.0 {
-            v.clear();
-            v.resize(size, 0.0);
-        }
+        vector.copy_from_slice(state.point());
+        self.0 = state.into_registers();
+
+        result
+    }
+
+    /// Begin SPSA optimizing function `target` subject to its constraints, starting at `vector`.
+    /// Optimized argument vector will be stored in the `vector` argument.
+    ///
+    /// Uses the augmented Lagrangian method: every outer iteration runs a whole optimization
+    /// with options produced by `options`, then updates the Lagrange multipliers.
+    ///
+    /// # Example
+    /// ```rust
+    /// use approx::assert_relative_eq;
+    /// use spsa::{AugmentedLagrangian, ConstrainedTarget, Optimizer, Options};
+    ///
+    /// struct Circle;
+    ///
+    /// impl ConstrainedTarget for Circle {
+    ///     fn objective(&mut self, data: &[f64]) -> f64 {
+    ///         data[0] + data[1]
+    ///     }
+    ///
+    ///     fn inequalities(&self) -> usize {
+    ///         1
+    ///     }
+    ///
+    ///     fn constraints(&mut self, data: &[f64], g: &mut [f64], _: &mut [f64]) {
+    ///         g[0] = data[0] * data[0] + data[1] * data[1] - 2.0;
+    ///     }
+    /// }
+    ///
+    /// let mut optimizer = Optimizer::new();
+    /// let mut input = [0.0, 0.0];
+    ///
+    /// let options = || Options { seed: Some(42), ..Options::default() };
+    /// let report = optimizer.optimize_constrained(Circle, &mut input, options, AugmentedLagrangian::default()).unwrap();
+    ///
+    /// assert_relative_eq!(input[0], 1.0, epsilon = 1e-3);
+    /// assert_relative_eq!(input[1], 1.0, epsilon = 1e-3);
+    /// assert!(report.violation < 1e-3);
+    /// ```
+    pub fn optimize_constrained<T: ConstrainedTarget>(&mut self, target: T, vector: &mut [f64], options: impl FnMut() -> Options, lagrangian: AugmentedLagrangian) -> Result<ConstrainedReport, SpsaError> {
+        constraint::optimize(self, target, vector, options, lagrangian)
+    }
+
+    /// Begin SPSA optimizing function `target` over a named [ParamSpace], starting at the initial values.
+    /// Returns the optimized [Params] along with the [Report].
+    ///
+    /// `target` is fed the parameter values in the order they were added to the space,
+    /// while [Target::iteration()] sees the normalized vector.
+    /// Bounds of the space replace [Options::bounds], and [Options::kinds] must not be set,
+    /// otherwise [SpsaError::InvalidKinds] is returned.
+    ///
+    /// # Example
+    /// ```rust
+    /// use approx::assert_relative_eq;
+    /// use spsa::{Optimizer, Options, ParamSpace, minimize};
+    ///
+    /// let space = ParamSpace::new()
+    ///     .param("rate", 1e-2).log_scale()
+    ///     .param("width", 100.0).scale(100.0);
+    ///
+    /// let params = Optimizer::new().optimize_space(minimize(|data| {
+    ///     let rate = data[0].ln() - f64::ln(1e-4);
+    ///     let width = (data[1] - 300.0) / 100.0;
+    ///     1.0 + rate * rate + width * width
+    /// }), &space, Options { seed: Some(42), ..Options::default() }).unwrap();
+    ///
+    /// assert_relative_eq!(params["rate"], 1e-4, max_relative = 1e-3);
+    /// assert_relative_eq!(params["width"], 300.0, epsilon = 1e-3);
+    /// ```
+    pub fn optimize_space<T: Target>(&mut self, target: T, space: &ParamSpace, options: Options) -> Result<Params, SpsaError> {
+        space::optimize(self, target, space, options)
+    }
+
+    /// Continue the optimization from a [Checkpoint] captured by [Target::checkpoint()].
+    /// Optimized argument vector will be stored in the `vector` argument.
+    ///
+    /// `options` should be the same as the ones the optimization has been started with,
+    /// see [SpsaState::resume()].
+    ///
+    /// # Panics
+    /// If the length of `vector` differs from the one the optimization has been started with
+    pub fn resume<T: Target>(&mut self, checkpoint: Checkpoint, target: T, vector: &mut [f64], options: Options) -> Result<Report, SpsaError> {
+        let mut state = SpsaState::resume_with_registers(checkpoint, options, std::mem::take(&mut self.0));
+        let result = algo::optimize(target, &mut state, |state| Some(state.checkpoint()));
+
+        vector.copy_from_slice(state.point());
+        self.0 = state.into_registers();
+
+        result
+    }
+
+    /// Same as [Optimizer::optimize()], but draws perturbations from a given random number generator.
+    /// [Seed](Options#structfield.seed) option is ignored, and no [checkpoints](Target::checkpoint()) are made.
+    ///
+    /// The generator is taken by value, pass `&mut rng` to keep using it afterwards.
+    /// It cannot be shared with a stochastic function during the optimization; for common random numbers,
+    /// draw the noise of the function from the seed given to [Target::evaluate_seeded()] instead.
+    pub fn optimize_with_rng<T: Target, R: RngCore>(&mut self, target: T, vector: &mut [f64], options: Options, rng: R) -> Result<Report, SpsaError> {
+        let mut state = SpsaState::with_registers(vector, options, rng, &mut self.0)?;
+        let result = algo::optimize(target, &mut state, |_| None);
+
+        vector.copy_from_slice(state.point());
+        self.0 = state.into_registers();
+
+        result
+    }
+
+    /// Same as [Optimizer::optimize()], but evaluates independent points in batches.
+    ///
+    /// Every batch holds both sides of a perturbation, both line search probes,
+    /// or repeated evaluations of the same point used for noise estimation.
+    pub fn optimize_batch<T: BatchTarget>(&mut self, target: T, vector: &mut [f64], options: Options) -> Result<Report, SpsaError> {
+        let rng = utils::seeded(options.seed);
+        let mut state = SpsaState::with_registers(vector, options, rng, &mut self.0)?;
+        let result = algo::optimize_batch(target, &mut state, |state| Some(state.checkpoint()));
+
+        vector.copy_from_slice(state.point());
+        self.0 = state.into_registers();
+
+        result
+    }
+
+    /// Same as [Optimizer::resume()], but evaluates independent points in batches, see [Optimizer::optimize_batch()].
+    ///
+    /// # Panics
+    /// If the length of `vector` differs from the one the optimization has been started with
+    pub fn resume_batch<T: BatchTarget>(&mut self, checkpoint: Checkpoint, target: T, vector: &mut [f64], options: Options) -> Result<Report, SpsaError> {
+        let mut state = SpsaState::resume_with_registers(checkpoint, options, std::mem::take(&mut self.0));
+        let result = algo::optimize_batch(target, &mut state, |state| Some(state.checkpoint()));
+
+        vector.copy_from_slice(state.point());
+        self.0 = state.into_registers();
+
+        result
+    }
+
+    /// Same as [Optimizer::optimize_batch()], but draws perturbations from a given random number generator.
+    /// [Seed](Options#structfield.seed) option is ignored, and no [checkpoints](BatchTarget::checkpoint()) are made.
+    pub fn optimize_batch_with_rng<T: BatchTarget, R: RngCore>(&mut self, target: T, vector: &mut [f64], options: Options, rng: R) -> Result<Report, SpsaError> {
+        let mut state = SpsaState::with_registers(vector, options, rng, &mut self.0)?;
+        let result = algo::optimize_batch(target, &mut state, |_| None);
+
+        vector.copy_from_slice(state.point());
+        self.0 = state.into_registers();
 
-        algo::optimize(target, options, vector, &mut self.0)
+        result
     }
 }
\ No newline at end of file
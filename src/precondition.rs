@@ -0,0 +1,193 @@
+use crate::vec::op;
+
+/// Gradient statistics passed to [Preconditioner::update()] after every gradient estimate
+#[non_exhaustive]
+pub struct Moments<'a> {
+    /// Latest gradient estimate
+    pub gradient: &'a [f64],
+
+    /// Momentum of the gradient, biased towards zero by `momentum_bias`
+    pub momentum: &'a [f64],
+
+    /// Bias of the momentum; divide by it to get an unbiased estimate
+    pub momentum_bias: f64,
+
+    /// Slow moving average of the gradient, biased towards zero by `bias`
+    pub slow: &'a [f64],
+
+    /// Diagonal of the latest Hessian sample,
+    /// if the preconditioner [asked](Preconditioner::curvature()) for it and it could be estimated
+    pub curvature: Option<&'a [f64]>,
+
+    /// Weight of the latest sample in slow moving averages, that is `1 - beta`
+    pub weight: f64,
+
+    /// Bias of slow moving averages, including the latest sample
+    pub bias: f64
+}
+
+/// Scales steps and perturbations of every dimension, see [Options::preconditioner](crate::Options#structfield.preconditioner)
+///
+/// Preconditioners keep no state of their own: their per-dimension statistics are kept by the optimizer,
+/// so they are saved along with a [Checkpoint](crate::Checkpoint). On restarts, they are [rescaled](Preconditioner::restart()).
+pub trait Preconditioner {
+
+    /// Update the per-dimension statistics with the latest gradient estimate
+    fn update(&self, stats: &mut [f64], moments: &Moments);
+
+    /// Scale a step or a perturbation in place
+    ///
+    /// `bias` is the bias of slow moving averages, `epsilon` is [Options::epsilon](crate::Options#structfield.epsilon).
+    fn apply(&self, stats: &[f64], bias: f64, epsilon: f64, v: &mut [f64]);
+
+    /// Whether the diagonal of the Hessian should be estimated,
+    /// at the cost of two more evaluations per iteration
+    fn curvature(&self) -> bool {
+        false
+    }
+
+    /// Rescale the per-dimension statistics on a restart, where slow moving averages and their bias
+    /// are scaled by `scale` to weigh the gradients seen so far less
+    ///
+    /// Defaults to scaling the statistics by `scale` as well, which suits slow moving averages.
+    fn restart(&self, stats: &mut [f64], scale: f64) {
+        op!(mut stats => stats * scale);
+    }
+}
+
+impl<P: Preconditioner + ?Sized> Preconditioner for Box<P> {
+    #[inline]
+    fn update(&self, stats: &mut [f64], moments: &Moments) {
+        (**self).update(stats, moments)
+    }
+
+    #[inline]
+    fn apply(&self, stats: &[f64], bias: f64, epsilon: f64, v: &mut [f64]) {
+        (**self).apply(stats, bias, epsilon, v)
+    }
+
+    #[inline]
+    fn curvature(&self) -> bool {
+        (**self).curvature()
+    }
+
+    #[inline]
+    fn restart(&self, stats: &mut [f64], scale: f64) {
+        (**self).restart(stats, scale)
+    }
+}
+
+/// Adaptive moment estimation: scales by the inverse root mean square of the gradient
+pub struct Adam;
+
+impl Preconditioner for Adam {
+    fn update(&self, stats: &mut [f64], moments: &Moments) {
+        let (slow, weight, bias) = (moments.slow, moments.weight, moments.bias);
+        op!(mut stats, slow => stats + weight * ((slow / bias) * (slow / bias) - stats));
+    }
+
+    fn apply(&self, stats: &[f64], bias: f64, epsilon: f64, v: &mut [f64]) {
+        op!(mut v, stats => v * (stats / bias + epsilon).rsqrte());
+    }
+}
+
+/// Scales by the inverse root of the sum of all squared gradients seen so far,
+/// so dimensions that have changed a lot take ever smaller steps
+///
+/// The sum is not a moving average and carries no bias, so it is kept as it is on restarts.
+pub struct AdaGrad;
+
+impl Preconditioner for AdaGrad {
+    fn update(&self, stats: &mut [f64], moments: &Moments) {
+        let gradient = moments.gradient;
+        op!(mut stats, gradient => stats + gradient * gradient);
+    }
+
+    fn apply(&self, stats: &[f64], _: f64, epsilon: f64, v: &mut [f64]) {
+        op!(mut v, stats => v * (stats + epsilon).rsqrte());
+    }
+
+    fn restart(&self, _: &mut [f64], _: f64) {}
+}
+
+/// Scales by the inverse root of the variance of the gradient around its momentum,
+/// taking large steps where the gradient is consistent and small ones where it's noisy
+pub struct AdaBelief;
+
+impl Preconditioner for AdaBelief {
+    fn update(&self, stats: &mut [f64], moments: &Moments) {
+        let (gradient, momentum, weight) = (moments.gradient, moments.momentum, moments.weight);
+        let b1 = moments.momentum_bias;
+
+        op!(mut stats, gradient, momentum => stats + weight * ((gradient - momentum / b1) * (gradient - momentum / b1) - stats));
+    }
+
+    fn apply(&self, stats: &[f64], bias: f64, epsilon: f64, v: &mut [f64]) {
+        op!(mut v, stats => v * (stats / bias + epsilon).rsqrte());
+    }
+}
+
+/// Scales by the inverse of the curvature of every dimension,
+/// estimated from the diagonal of 2SPSA Hessian samples
+///
+/// Needs two more evaluations per iteration. Dimensions without a curvature estimate yet are left as they are.
+/// Curvature samples are fewer than gradient estimates, so their moving average starts at the first sample
+/// instead of being divided by the bias of gradient statistics.
+pub struct DiagonalHessian;
+
+impl Preconditioner for DiagonalHessian {
+    fn update(&self, stats: &mut [f64], moments: &Moments) {
+        if let Some(curvature) = moments.curvature {
+            let weight = moments.weight;
+
+            for (h, c) in stats.iter_mut().zip(curvature) {
+                *h = if *h > 0.0 { *h + weight * (c.abs() - *h) } else { c.abs() };
+            }
+        }
+    }
+
+    fn apply(&self, stats: &[f64], _: f64, epsilon: f64, v: &mut [f64]) {
+        for (v, h) in v.iter_mut().zip(stats) {
+            if *h > 0.0 {
+                *v /= h + epsilon;
+            }
+        }
+    }
+
+    fn curvature(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiagonalHessian, Moments, Preconditioner};
+
+    fn moments(curvature: Option<&[f64]>, bias: f64) -> Moments<'_> {
+        Moments {
+            gradient: &[0.0],
+            momentum: &[0.0],
+            momentum_bias: bias,
+            slow: &[0.0],
+            curvature,
+            weight: 1e-3,
+            bias
+        }
+    }
+
+    #[test]
+    fn diagonal_hessian() {
+        let mut stats = [0.0];
+
+        // gradient estimates without curvature samples leave the estimate alone, whatever their bias
+        DiagonalHessian.update(&mut stats, &moments(None, 0.01));
+        DiagonalHessian.update(&mut stats, &moments(Some(&[-4.0]), 0.02));
+        DiagonalHessian.update(&mut stats, &moments(None, 0.03));
+        DiagonalHessian.update(&mut stats, &moments(Some(&[4.0]), 0.04));
+
+        let mut v = [1.0];
+        DiagonalHessian.apply(&stats, 0.04, 0.0, &mut v);
+
+        assert_eq!(v, [0.25]);
+    }
+}
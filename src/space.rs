@@ -0,0 +1,250 @@
+use std::ops::Index;
+use crate::{Checkpoint, Control, Iteration, Optimizer, Options, Report, Role, SpsaError, Target};
+
+/// Named parameters of a function, optimized in normalized coordinates
+///
+/// Every parameter is shifted by its initial value and divided by its scale,
+/// so the optimizer sees a vector starting at zero where all dimensions are equally sensitive.
+/// Log-scale parameters are normalized in logarithmic space, so their scale is a factor of `e`.
+///
+/// Parameters are added by [param](ParamSpace::param()), other methods configure the last added one.
+///
+/// # Example
+/// ```rust
+/// use spsa::ParamSpace;
+///
+/// let space = ParamSpace::new()
+///     .param("learning_rate", 1e-3).log_scale().bounds(1e-6, 1.0)
+///     .param("width", 64.0).scale(16.0).bounds(1.0, 1024.0);
+///
+/// assert_eq!(space.len(), 2);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ParamSpace {
+    params: Vec<Param>
+}
+
+#[derive(Debug, Clone)]
+struct Param {
+    name: String,
+    initial: f64,
+    scale: f64,
+    bounds: Option<(f64, f64)>,
+    log: bool
+}
+
+impl Param {
+    fn transform(&self, v: f64) -> f64 {
+        if self.log { v.ln() } else { v }
+    }
+
+    fn normalize(&self, v: f64) -> f64 {
+        (self.transform(v) - self.transform(self.initial)) / self.scale
+    }
+
+    /// Log-scale parameters have no logarithm at zero or below
+    fn check_log(&self) {
+        if self.log {
+            assert!(self.initial > 0.0, "log-scale parameter `{}` must have a positive initial value", self.name);
+            assert!(self.bounds.is_none_or(|(lower, _)| lower > 0.0), "log-scale parameter `{}` must have a positive lower bound", self.name);
+        }
+    }
+
+    fn denormalize(&self, u: f64) -> f64 {
+        let v = self.transform(self.initial) + u * self.scale;
+        let v = if self.log { v.exp() } else { v };
+
+        match self.bounds { // guard against rounding errors of the transform
+            Some((lower, upper)) => v.clamp(lower, upper),
+            None => v
+        }
+    }
+}
+
+impl ParamSpace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a parameter with a given name and initial value
+    pub fn param(mut self, name: impl Into<String>, initial: f64) -> Self {
+        self.params.push(Param {
+            name: name.into(),
+            initial,
+            scale: 1.0,
+            bounds: None,
+            log: false
+        });
+
+        self
+    }
+
+    /// Set the typical size of changes of the last added parameter (defaults to 1)
+    pub fn scale(mut self, scale: f64) -> Self {
+        assert!(scale > 0.0, "scale must be positive");
+        self.last().scale = scale;
+        self
+    }
+
+    /// Set the lower and upper bound of the last added parameter
+    pub fn bounds(mut self, lower: f64, upper: f64) -> Self {
+        assert!(lower <= upper, "lower bound must not exceed upper bound");
+        self.last().bounds = Some((lower, upper));
+        self.last().check_log();
+        self
+    }
+
+    /// Optimize the last added parameter in logarithmic space;
+    /// its initial value and lower bound, if any, must be positive
+    pub fn log_scale(mut self) -> Self {
+        self.last().log = true;
+        self.last().check_log();
+        self
+    }
+
+    fn last(&mut self) -> &mut Param {
+        self.params.last_mut().expect("no parameter has been added")
+    }
+
+    /// Number of parameters
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Names of the parameters, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.params.iter().map(|p| p.name.as_str())
+    }
+
+    /// Map parameter values to the normalized vector seen by the optimizer
+    pub fn normalize(&self, values: &[f64], normalized: &mut [f64]) {
+        for ((p, v), u) in self.params.iter().zip(values).zip(normalized) {
+            *u = p.normalize(*v);
+        }
+    }
+
+    /// Map a normalized vector back to parameter values
+    pub fn denormalize(&self, normalized: &[f64], values: &mut [f64]) {
+        for ((p, u), v) in self.params.iter().zip(normalized).zip(values) {
+            *v = p.denormalize(*u);
+        }
+    }
+
+    /// Bounds of the normalized vector, if any parameter is bounded
+    fn bounds_normalized(&self) -> Option<Vec<(f64, f64)>> {
+        if self.params.iter().all(|p| p.bounds.is_none()) {
+            return None;
+        }
+
+        let bounds = self.params.iter()
+            .map(|p| match p.bounds {
+                Some((lower, upper)) => (p.normalize(lower), p.normalize(upper)),
+                None => (f64::NEG_INFINITY, f64::INFINITY)
+            })
+            .collect();
+
+        Some(bounds)
+    }
+}
+
+/// Optimized parameter values returned by [Optimizer::optimize_space()]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Params {
+    /// Report of the optimization process
+    pub report: Report,
+
+    /// Parameter names, in the order they were added
+    pub names: Vec<String>,
+
+    /// Optimized parameter values
+    pub values: Vec<f64>
+}
+
+impl Params {
+    /// Optimized value of the parameter with a given name
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.names.iter().position(|n| n == name).map(|i| self.values[i])
+    }
+
+    /// Pairs of parameter names and optimized values
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.names.iter().map(String::as_str).zip(self.values.iter().copied())
+    }
+}
+
+impl Index<&str> for Params {
+    type Output = f64;
+
+    fn index(&self, name: &str) -> &f64 {
+        let i = self.names.iter().position(|n| n == name);
+        &self.values[i.unwrap_or_else(|| panic!("no parameter named `{}`", name))]
+    }
+}
+
+/// Feeds the parameter values to the source target instead of the normalized vector
+struct Scaled<'a, T> {
+    source: T,
+    space: &'a ParamSpace,
+    values: Vec<f64>
+}
+
+impl<'a, T: Target> Target for Scaled<'a, T> {
+    fn evaluate(&mut self, data: &[f64]) -> f64 {
+        self.space.denormalize(data, &mut self.values);
+        self.source.evaluate(&self.values)
+    }
+
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.space.denormalize(data, &mut self.values);
+        self.source.evaluate_seeded(&self.values, seed)
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.source.is_deterministic()
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        self.source.evaluations_left()
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
+    }
+}
+
+pub fn optimize<T: Target>(optimizer: &mut Optimizer, target: T, space: &ParamSpace, mut options: Options) -> Result<Params, SpsaError> {
+    if options.kinds.is_some() { // discrete values would be rounded in normalized coordinates
+        return Err(SpsaError::InvalidKinds);
+    }
+
+    let mut vector = vec![0.0; space.len()];
+    options.bounds = space.bounds_normalized();
+
+    let mut inner = Scaled {
+        source: target,
+        space,
+        values: vec![0.0; space.len()]
+    };
+
+    let report = optimizer.optimize(&mut inner, &mut vector, options)?;
+    space.denormalize(&vector, &mut inner.values);
+
+    Ok(Params {
+        report,
+        names: space.names().map(String::from).collect(),
+        values: inner.values
+    })
+}
@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+use crate::{Direction, StopReason};
+
+/// Optimizer state passed to [StopCriterion::check()] after every iteration
+#[non_exhaustive]
+pub struct Status {
+    /// Number of iterations run so far
+    pub iteration: usize,
+
+    /// Number of [Target::evaluate()](crate::Target::evaluate()) calls made so far
+    pub evaluations: usize,
+
+    /// Whether the function is maximized or minimized; function values below follow it
+    pub direction: Direction,
+
+    /// Current smoothed function value
+    pub value: f64,
+
+    /// Best smoothed function value found so far
+    pub best: f64,
+
+    /// Norm of the last step taken
+    pub step: f64,
+
+    /// Current learning rate
+    pub learning_rate: f64
+}
+
+/// Decides when the optimization process should stop
+///
+/// Checked after every iteration in addition to the [iteration limit](crate::Options#structfield.iterations).
+pub trait StopCriterion {
+
+    /// Return `Some(reason)` to stop the optimization
+    fn check(&mut self, status: &Status) -> Option<StopReason>;
+}
+
+impl<S: StopCriterion + ?Sized> StopCriterion for Box<S> {
+    #[inline]
+    fn check(&mut self, status: &Status) -> Option<StopReason> {
+        (**self).check(status)
+    }
+}
+
+/// Stops when the number of function evaluations reaches a given budget
+///
+/// Since the criterion is checked between iterations, the budget may be exceeded
+/// by the evaluations made during a single iteration.
+pub struct MaxEvaluations(pub usize);
+
+impl StopCriterion for MaxEvaluations {
+    fn check(&mut self, status: &Status) -> Option<StopReason> {
+        if status.evaluations >= self.0 {
+            Some(StopReason::Evaluations)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stops when a given point in time is reached
+pub struct Deadline(pub Instant);
+
+impl StopCriterion for Deadline {
+    fn check(&mut self, _: &Status) -> Option<StopReason> {
+        if Instant::now() >= self.0 {
+            Some(StopReason::Deadline)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stops when the best function value reaches a given target,
+/// from below when maximizing and from above when minimizing
+pub struct TargetValue(pub f64);
+
+impl StopCriterion for TargetValue {
+    fn check(&mut self, status: &Status) -> Option<StopReason> {
+        if status.direction.sign() * (status.best - self.0) >= 0.0 {
+            Some(StopReason::TargetValue)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stops when the best function value has improved by less than
+/// `tolerance` (relative) over the last `window` iterations
+pub struct NoImprovement {
+    window: usize,
+    tolerance: f64,
+    history: VecDeque<f64>
+}
+
+impl NoImprovement {
+    pub fn new(window: usize, tolerance: f64) -> Self {
+        Self {
+            window,
+            tolerance,
+            history: VecDeque::with_capacity(window + 1)
+        }
+    }
+}
+
+impl StopCriterion for NoImprovement {
+    fn check(&mut self, status: &Status) -> Option<StopReason> {
+        self.history.push_back(status.best);
+
+        if self.history.len() <= self.window {
+            return None;
+        }
+
+        let old = self.history.pop_front().unwrap();
+
+        if status.direction.sign() * (status.best - old) <= self.tolerance * old.abs() {
+            Some(StopReason::NoImprovement)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stops when the norm of the step taken falls below a given tolerance
+pub struct StepSize(pub f64);
+
+impl StopCriterion for StepSize {
+    fn check(&mut self, status: &Status) -> Option<StopReason> {
+        if status.step < self.0 {
+            Some(StopReason::StepSize)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stops when any of the criteria says so
+///
+/// Every criterion is checked on every iteration, so stateful criteria stay up to date.
+pub struct Any(pub Vec<Box<dyn StopCriterion>>);
+
+impl StopCriterion for Any {
+    fn check(&mut self, status: &Status) -> Option<StopReason> {
+        let mut reason = None;
+
+        for c in &mut self.0 {
+            reason = reason.or(c.check(status));
+        }
+
+        reason
+    }
+}
+
+/// Stops when all of the criteria say so
+///
+/// Every criterion is checked on every iteration, so stateful criteria stay up to date.
+pub struct All(pub Vec<Box<dyn StopCriterion>>);
+
+impl StopCriterion for All {
+    fn check(&mut self, status: &Status) -> Option<StopReason> {
+        let mut reason = None;
+        let mut stop = true;
+
+        for c in &mut self.0 {
+            match c.check(status) {
+                Some(r) => reason = Some(r),
+                None => stop = false
+            }
+        }
+
+        reason.filter(|_| stop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Direction, StopReason};
+    use super::{All, Any, MaxEvaluations, NoImprovement, Status, StopCriterion, TargetValue};
+
+    fn status(evaluations: usize, best: f64) -> Status {
+        Status {
+            iteration: 0,
+            evaluations,
+            direction: Direction::Maximize,
+            value: best,
+            best,
+            step: 1.0,
+            learning_rate: 1.0
+        }
+    }
+
+    #[test]
+    fn no_improvement() {
+        let mut c = NoImprovement::new(2, 1e-3);
+
+        assert_eq!(c.check(&status(0, 1.0)), None);
+        assert_eq!(c.check(&status(0, 2.0)), None);
+        assert_eq!(c.check(&status(0, 2.0)), None);
+        assert_eq!(c.check(&status(0, 2.0)), Some(StopReason::NoImprovement));
+    }
+
+    #[test]
+    fn combinators() {
+        let mut any = Any(vec![Box::new(MaxEvaluations(10)), Box::new(TargetValue(1.0))]);
+        let mut all = All(vec![Box::new(MaxEvaluations(10)), Box::new(TargetValue(1.0))]);
+
+        assert_eq!(any.check(&status(5, 0.0)), None);
+        assert_eq!(any.check(&status(5, 1.0)), Some(StopReason::TargetValue));
+        assert_eq!(all.check(&status(5, 1.0)), None);
+        assert_eq!(all.check(&status(10, 1.0)), Some(StopReason::TargetValue));
+    }
+}
@@ -1,6 +1,9 @@
-use rand::{Rng, SeedableRng, thread_rng};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use rand::{Rng, RngCore, SeedableRng, thread_rng};
 use rand::rngs::StdRng;
-use crate::Iteration;
+use crate::{Checkpoint, Control, Iteration, Role};
 use crate::vec::op;
 use crate::utils::rand;
 
@@ -11,9 +14,43 @@ pub trait Target {
     /// Return `f64::NAN` if we're out of bounds
     fn evaluate(&mut self, data: &[f64]) -> f64;
 
+    /// Evaluate the function at a given point using a given random seed
+    ///
+    /// Both sides of a perturbation, as well as both line search probes, are evaluated with the same seed,
+    /// so stochastic functions may use common random numbers to greatly reduce the variance of the gradient estimate.
+    #[allow(unused_variables)]
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.evaluate(data)
+    }
+
     /// Called after every optimizer iteration
+    /// Returned [Control] value decides how the optimization proceeds
+    #[allow(unused_variables)]
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        Control::Continue
+    }
+
+    /// Called every [checkpoint interval](crate::Options#structfield.checkpoint_interval) iterations
+    /// Checkpoint may be saved to [resume](crate::Optimizer::resume()) the optimization later
     #[allow(unused_variables)]
-    fn iteration(&mut self, iter: Iteration) {}
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {}
+
+    /// Whether the function always returns the same value at the same point, see [Options::deterministic](crate::Options#structfield.deterministic)
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+
+    /// Number of evaluations the function may still take, if limited, see [Budget]
+    ///
+    /// The optimization stops with [StopReason::Evaluations](crate::StopReason::Evaluations) before they run out,
+    /// keeping enough of them to evaluate the optimized point whenever possible.
+    fn evaluations_left(&self) -> Option<usize> {
+        None
+    }
+
+    /// Called before every evaluation with the [Role] of the point about to be evaluated
+    #[allow(unused_variables)]
+    fn evaluating(&mut self, role: Role) {}
 }
 
 impl<'a, T: Target> Target for &'a mut T {
@@ -23,9 +60,103 @@ impl<'a, T: Target> Target for &'a mut T {
     }
 
     #[inline]
-    fn iteration(&mut self, iter: Iteration) {
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        (*self).evaluate_seeded(data, seed)
+    }
+
+    #[inline]
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        (*self).iteration(iter)
+    }
+
+    #[inline]
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        (*self).checkpoint(checkpoint)
+    }
+
+    #[inline]
+    fn is_deterministic(&self) -> bool {
+        (**self).is_deterministic()
+    }
+
+    #[inline]
+    fn evaluations_left(&self) -> Option<usize> {
+        (**self).evaluations_left()
+    }
+
+    #[inline]
+    fn evaluating(&mut self, role: Role) {
+        (*self).evaluating(role)
+    }
+}
+
+/// Represents a function to optimize that can evaluate several points at once,
+/// e.g. both sides of a perturbation in parallel
+pub trait BatchTarget {
+
+    /// Evaluate the function at every point, storing the results in `out`
+    /// Store `f64::NAN` if a point is out of bounds
+    fn evaluate_batch(&mut self, points: &[&[f64]], out: &mut [f64]);
+
+    /// Evaluate the function at every point using a given random seed, see [Target::evaluate_seeded()]
+    #[allow(unused_variables)]
+    fn evaluate_batch_seeded(&mut self, points: &[&[f64]], out: &mut [f64], seed: u64) {
+        self.evaluate_batch(points, out)
+    }
+
+    /// Called after every optimizer iteration
+    /// Returned [Control] value decides how the optimization proceeds
+    #[allow(unused_variables)]
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        Control::Continue
+    }
+
+    /// Called every [checkpoint interval](crate::Options#structfield.checkpoint_interval) iterations
+    /// Checkpoint may be saved to [resume](crate::Optimizer::resume_batch()) the optimization later
+    #[allow(unused_variables)]
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {}
+
+    /// Whether the function always returns the same value at the same point, see [Options::deterministic](crate::Options#structfield.deterministic)
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+
+    /// Number of evaluations the function may still take, if limited, see [Target::evaluations_left()]
+    fn evaluations_left(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<'a, T: BatchTarget> BatchTarget for &'a mut T {
+    #[inline]
+    fn evaluate_batch(&mut self, points: &[&[f64]], out: &mut [f64]) {
+        (*self).evaluate_batch(points, out)
+    }
+
+    #[inline]
+    fn evaluate_batch_seeded(&mut self, points: &[&[f64]], out: &mut [f64], seed: u64) {
+        (*self).evaluate_batch_seeded(points, out, seed)
+    }
+
+    #[inline]
+    fn iteration(&mut self, iter: Iteration) -> Control {
         (*self).iteration(iter)
     }
+
+    #[inline]
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        (*self).checkpoint(checkpoint)
+    }
+
+    #[inline]
+    fn is_deterministic(&self) -> bool {
+        (**self).is_deterministic()
+    }
+
+    #[inline]
+    fn evaluations_left(&self) -> Option<usize> {
+        (**self).evaluations_left()
+    }
 }
 
 /// Decorator that calls underlying function multiple times to smooth out the noise
@@ -46,25 +177,73 @@ impl<T: Target> Target for Oversample<T> {
         v / (n as f64)
     }
 
-    fn iteration(&mut self, iter: Iteration) {
-        self.source.iteration(iter);
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        let n = self.count + 1;
+        let mut v = 0.0;
+
+        for i in 0..n {
+            v += self.source.evaluate_seeded(data, seed.wrapping_add(i as u64));
+        }
+
+        v / (n as f64)
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.source.is_deterministic()
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        self.source.evaluations_left().map(|left| left / (self.count + 1))
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
     }
 }
 
 /// Decorator that adds random noise to function output
-pub struct OutputNoise<T> {
+///
+/// [Seeded](Target::evaluate_seeded()) evaluations draw the noise from the seed,
+/// so evaluations sharing a seed share the noise as well.
+pub struct OutputNoise<T, R = StdRng> {
     source: T,
-    gen: StdRng,
+    gen: R,
+    salt: u64,
     amplitude: f64
 }
 
-impl<T: Target> Target for OutputNoise<T> {
+impl<T: Target, R: RngCore> Target for OutputNoise<T, R> {
     fn evaluate(&mut self, data: &[f64]) -> f64 {
         self.source.evaluate(data) + self.gen.gen_range(-self.amplitude..self.amplitude)
     }
 
-    fn iteration(&mut self, iter: Iteration) {
-        self.source.iteration(iter);
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        let mut gen = StdRng::seed_from_u64(seed ^ self.salt);
+        self.source.evaluate_seeded(data, seed) + gen.gen_range(-self.amplitude..self.amplitude)
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        self.source.evaluations_left()
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
     }
 }
 
@@ -78,32 +257,419 @@ impl<T: Target> Target for OutputNoise<T> {
 /// If the noise is sufficiently high, and there is a general trend in
 /// the direction of the basins towards the best basin,
 /// then this will converge to the locally best basin.
-pub struct InputNoise<T> {
+///
+/// [Seeded](Target::evaluate_seeded()) evaluations draw the noise from the seed,
+/// so evaluations sharing a seed share the noise as well.
+pub struct InputNoise<T, R = StdRng> {
     source: T,
-    gen: StdRng,
+    gen: R,
+    salt: u64,
     amplitude: f64,
     buffer: Vec<f64>
 }
 
-impl<T: Target> Target for InputNoise<T> {
-    fn evaluate(&mut self, data: &[f64]) -> f64 {
+impl<T: Target, R: RngCore> InputNoise<T, R> {
+    fn sample(&mut self, data: &[f64], seed: Option<u64>) -> f64 {
         let buffer = &mut self.buffer;
-        let rng = &mut self.gen;
         let amp = self.amplitude;
 
-        op!(mut buffer => rand(rng, -1.0..1.0) * amp);
+        buffer.resize(data.len(), 0.0);
+
+        match seed {
+            Some(seed) => {
+                let rng = &mut StdRng::seed_from_u64(seed ^ self.salt);
+                op!(mut buffer => rand(rng, -1.0..1.0) * amp);
+            },
+            None => {
+                let rng = &mut self.gen;
+                op!(mut buffer => rand(rng, -1.0..1.0) * amp);
+            }
+        }
 
         op!(mut buffer, data => buffer + data);
-        let up = self.source.evaluate(buffer);
+        let up = match seed {
+            Some(seed) => self.source.evaluate_seeded(buffer, seed),
+            None => self.source.evaluate(buffer)
+        };
 
         op!(mut buffer, data => 2.0 * data - buffer);
-        let down = self.source.evaluate(buffer);
+        let down = match seed {
+            Some(seed) => self.source.evaluate_seeded(buffer, seed),
+            None => self.source.evaluate(buffer)
+        };
 
         (up + down) * 0.5
     }
+}
+
+impl<T: Target, R: RngCore> Target for InputNoise<T, R> {
+    fn evaluate(&mut self, data: &[f64]) -> f64 {
+        self.sample(data, None)
+    }
+
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.sample(data, Some(seed))
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        self.source.evaluations_left().map(|left| left / 2)
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
+    }
+}
+
+/// Decorator that remembers the values of the most recently evaluated points,
+/// so points evaluated again with bit-identical coordinates are not evaluated by the underlying function
+///
+/// Only suited for deterministic functions: seeds are ignored when looking values up.
+pub struct Cached<T> {
+    source: T,
+    capacity: usize,
+    entries: HashMap<Vec<u64>, (f64, u64)>,
+    order: BTreeMap<u64, Vec<u64>>,
+    tick: u64,
+    hits: usize,
+    misses: usize
+}
+
+impl<T: Target> Cached<T> {
+    /// Number of evaluations answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of evaluations passed to the underlying function
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    fn lookup(&mut self, data: &[f64], evaluate: impl FnOnce(&mut T, &[f64]) -> f64) -> f64 {
+        let key: Vec<u64> = data.iter().map(|v| v.to_bits()).collect();
+        self.tick += 1;
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.order.remove(&entry.1);
+            self.order.insert(self.tick, key);
+            entry.1 = self.tick;
+
+            self.hits += 1;
+            return entry.0;
+        }
+
+        self.misses += 1;
+        let value = evaluate(&mut self.source, data);
+
+        if self.capacity == 0 {
+            return value;
+        }
+
+        if self.entries.len() >= self.capacity { // evict the least recently used point
+            if let Some(oldest) = self.order.keys().next().copied() {
+                let key = self.order.remove(&oldest).unwrap();
+                self.entries.remove(&key);
+            }
+        }
+
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+        value
+    }
+}
+
+impl<T: Target> Target for Cached<T> {
+    fn evaluate(&mut self, data: &[f64]) -> f64 {
+        self.lookup(data, |source, data| source.evaluate(data))
+    }
+
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.lookup(data, |source, data| source.evaluate_seeded(data, seed))
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.source.is_deterministic()
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        self.source.evaluations_left()
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
+    }
+}
+
+/// Decorator that counts evaluations of the underlying function and the time spent in them
+pub struct Counted<T> {
+    source: T,
+    calls: usize,
+    nans: usize,
+    elapsed: Duration
+}
+
+impl<T: Target> Counted<T> {
+    /// Number of evaluations
+    pub fn calls(&self) -> usize {
+        self.calls
+    }
+
+    /// Number of evaluations that returned `f64::NAN`
+    pub fn nans(&self) -> usize {
+        self.nans
+    }
+
+    /// Total time spent evaluating
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    fn count(&mut self, data: &[f64], evaluate: impl FnOnce(&mut T, &[f64]) -> f64) -> f64 {
+        let start = Instant::now();
+        let value = evaluate(&mut self.source, data);
+        self.elapsed += start.elapsed();
+
+        self.calls += 1;
+
+        if value.is_nan() {
+            self.nans += 1;
+        }
+
+        value
+    }
+}
+
+impl<T: Target> Target for Counted<T> {
+    fn evaluate(&mut self, data: &[f64]) -> f64 {
+        self.count(data, |source, data| source.evaluate(data))
+    }
+
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.count(data, |source, data| source.evaluate_seeded(data, seed))
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.source.is_deterministic()
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        self.source.evaluations_left()
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
+    }
+}
+
+/// Decorator that limits the number of evaluations of the underlying function
+///
+/// The optimization stops with [StopReason::Evaluations](crate::StopReason::Evaluations) once the remaining evaluations
+/// may not cover another iteration along with the evaluation of the optimized point, see [Target::evaluations_left()].
+/// Evaluations past the budget, e.g. by decorators that do not report it, return `f64::NAN`.
+pub struct Budget<T> {
+    source: T,
+    remaining: usize
+}
+
+impl<T: Target> Budget<T> {
+    /// Number of evaluations left
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn spend(&mut self, data: &[f64], evaluate: impl FnOnce(&mut T, &[f64]) -> f64) -> f64 {
+        if self.remaining == 0 {
+            return f64::NAN;
+        }
+
+        self.remaining -= 1;
+        evaluate(&mut self.source, data)
+    }
+}
+
+impl<T: Target> Target for Budget<T> {
+    fn evaluate(&mut self, data: &[f64]) -> f64 {
+        self.spend(data, |source, data| source.evaluate(data))
+    }
+
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.spend(data, |source, data| source.evaluate_seeded(data, seed))
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.source.is_deterministic()
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        Some(self.source.evaluations_left().map_or(self.remaining, |left| left.min(self.remaining)))
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.source.evaluating(role)
+    }
+}
+
+/// Single evaluation stored by [Record]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Evaluation {
+    /// Iteration the point has been evaluated in, counting from zero
+    pub iteration: usize,
+
+    /// Purpose of the point
+    pub role: Role,
+
+    /// Evaluated point
+    pub point: Vec<f64>,
+
+    /// Function value at the point
+    pub value: f64
+}
+
+/// Decorator that records every evaluated point along with its value, iteration and [Role]
+///
+/// Roles are only known when optimizing with [Optimizer::optimize()](crate::Optimizer::optimize())
+/// and similar methods, see [Target::evaluating()].
+pub struct Record<T> {
+    source: T,
+    history: Vec<Evaluation>,
+    iteration: usize,
+    role: Role
+}
+
+impl<T: Target> Record<T> {
+    /// Evaluations in the order they were made
+    pub fn history(&self) -> &[Evaluation] {
+        &self.history
+    }
+
+    /// Write the evaluations as CSV, with a header and a column for every dimension
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(out, "iteration,role,value")?;
+
+        for i in 0..self.history.first().map_or(0, |e| e.point.len()) {
+            write!(out, ",x{}", i)?;
+        }
+
+        writeln!(out)?;
+
+        for e in &self.history {
+            write!(out, "{},{},{:?}", e.iteration, e.role, e.value)?;
+
+            for v in &e.point {
+                write!(out, ",{:?}", v)?;
+            }
+
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the evaluations as JSON Lines, one object per evaluation; non-finite values are written as `null`
+    pub fn write_jsonl<W: Write>(&self, mut out: W) -> io::Result<()> {
+        fn number<W: Write>(out: &mut W, v: f64) -> io::Result<()> {
+            if v.is_finite() {
+                write!(out, "{:?}", v)
+            } else {
+                write!(out, "null")
+            }
+        }
+
+        for e in &self.history {
+            write!(out, "{{\"iteration\":{},\"role\":\"{}\",\"value\":", e.iteration, e.role)?;
+            number(&mut out, e.value)?;
+            write!(out, ",\"point\":[")?;
 
-    fn iteration(&mut self, iter: Iteration) {
-        self.source.iteration(iter);
+            for (i, v) in e.point.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+
+                number(&mut out, *v)?;
+            }
+
+            writeln!(out, "]}}")?;
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, data: &[f64], evaluate: impl FnOnce(&mut T, &[f64]) -> f64) -> f64 {
+        let value = evaluate(&mut self.source, data);
+
+        self.history.push(Evaluation {
+            iteration: self.iteration,
+            role: self.role,
+            point: data.to_vec(),
+            value
+        });
+
+        value
+    }
+}
+
+impl<T: Target> Target for Record<T> {
+    fn evaluate(&mut self, data: &[f64]) -> f64 {
+        self.push(data, |source, data| source.evaluate(data))
+    }
+
+    fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+        self.push(data, |source, data| source.evaluate_seeded(data, seed))
+    }
+
+    fn iteration(&mut self, iter: Iteration) -> Control {
+        self.iteration = iter.iteration + 1;
+        self.source.iteration(iter)
+    }
+
+    fn checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.source.checkpoint(checkpoint)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.source.is_deterministic()
+    }
+
+    fn evaluations_left(&self) -> Option<usize> {
+        self.source.evaluations_left()
+    }
+
+    fn evaluating(&mut self, role: Role) {
+        self.role = role;
+        self.source.evaluating(role)
     }
 }
 
@@ -111,6 +677,9 @@ impl<T: Target> Target for InputNoise<T> {
 pub struct Maximize<F>(pub F);
 
 /// Target that minimizes a given function output
+///
+/// Negates the function, so reported values are negated as well;
+/// use [Direction::Minimize](crate::Direction) to keep them as they are.
 pub struct Minimize<F>(pub F);
 
 impl<F: FnMut(&[f64]) -> f64> Target for Maximize<F> {
@@ -134,7 +703,7 @@ impl<F: FnMut(&[f64]) -> f64> Target for Minimize<F> {
 /// let mut optimizer = Optimizer::new();
 /// let mut input = [0.0, 0.0];
 ///
-/// optimizer.optimize(maximize(|data| 1.0 - (data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default());
+/// optimizer.optimize(maximize(|data| 1.0 - (data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default()).unwrap();
 ///
 /// assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
 /// assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
@@ -152,7 +721,7 @@ pub fn maximize<F: FnMut(&[f64]) -> f64>(f: F) -> Maximize<F> {
 /// let mut optimizer = Optimizer::new();
 /// let mut input = [0.0, 0.0];
 ///
-/// optimizer.optimize(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default());
+/// optimizer.optimize(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default()).unwrap();
 ///
 /// assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
 /// assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
@@ -161,6 +730,43 @@ pub fn minimize<F: FnMut(&[f64]) -> f64>(f: F) -> Minimize<F> {
     Minimize(f)
 }
 
+/// Batch target that maximizes a given thread-safe function output,
+/// evaluating all points of a batch in parallel
+#[cfg(feature = "parallel")]
+pub struct Parallel<F>(pub F);
+
+#[cfg(feature = "parallel")]
+impl<F: Fn(&[f64]) -> f64 + Sync> BatchTarget for Parallel<F> {
+    fn evaluate_batch(&mut self, points: &[&[f64]], out: &mut [f64]) {
+        use rayon::prelude::*;
+
+        let f = &self.0;
+
+        points.par_iter()
+            .zip(out.par_iter_mut())
+            .for_each(|(point, out)| *out = f(point));
+    }
+}
+
+/// Creates a batch target that maximizes given function output, evaluating points in parallel
+/// # Example
+/// ```rust
+/// use approx::assert_relative_eq;
+/// use spsa::{Optimizer, Options, parallel};
+///
+/// let mut optimizer = Optimizer::new();
+/// let mut input = [0.0, 0.0];
+///
+/// optimizer.optimize_batch(parallel(|data| 1.0 - (data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default()).unwrap();
+///
+/// assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
+/// assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn parallel<F: Fn(&[f64]) -> f64 + Sync>(f: F) -> Parallel<F> {
+    Parallel(f)
+}
+
 /// Extension methods for [`Target`]
 pub trait TargetExt where Self: Sized {
 
@@ -178,9 +784,20 @@ pub trait TargetExt where Self: Sized {
 
     /// Creates a new [`Target`] that adds slight amount of noise to its output.
     fn output_noise(self, amplitude: f64) -> OutputNoise<Self> {
+        self.output_noise_with_rng(amplitude, StdRng::from_rng(thread_rng()).unwrap())
+    }
+
+    /// Same as [output_noise](TargetExt::output_noise()), but the noise is generated from a given seed.
+    fn output_noise_seeded(self, amplitude: f64, seed: u64) -> OutputNoise<Self> {
+        self.output_noise_with_rng(amplitude, StdRng::seed_from_u64(seed))
+    }
+
+    /// Same as [output_noise](TargetExt::output_noise()), but the noise is drawn from a given random number generator.
+    fn output_noise_with_rng<R: RngCore>(self, amplitude: f64, mut rng: R) -> OutputNoise<Self, R> {
         OutputNoise {
             source: self,
-            gen: StdRng::from_rng(thread_rng()).unwrap(),
+            salt: rng.next_u64(),
+            gen: rng,
             amplitude
         }
     }
@@ -196,13 +813,69 @@ pub trait TargetExt where Self: Sized {
     /// the direction of the basins towards the best basin,
     /// then this will converge to the locally best basin.
     fn input_noise(self, amplitude: f64) -> InputNoise<Self> {
+        self.input_noise_with_rng(amplitude, StdRng::from_rng(thread_rng()).unwrap())
+    }
+
+    /// Same as [input_noise](TargetExt::input_noise()), but the noise is generated from a given seed.
+    fn input_noise_seeded(self, amplitude: f64, seed: u64) -> InputNoise<Self> {
+        self.input_noise_with_rng(amplitude, StdRng::seed_from_u64(seed))
+    }
+
+    /// Same as [input_noise](TargetExt::input_noise()), but the noise is drawn from a given random number generator.
+    fn input_noise_with_rng<R: RngCore>(self, amplitude: f64, mut rng: R) -> InputNoise<Self, R> {
         InputNoise {
             source: self,
             buffer: Vec::new(),
-            gen: StdRng::from_rng(thread_rng()).unwrap(),
+            salt: rng.next_u64(),
+            gen: rng,
             amplitude
         }
     }
+
+    /// Creates a new [`Target`] that remembers the values of up to `capacity` most recently evaluated points,
+    /// see [Cached].
+    ///
+    /// Repeated evaluations of the same point are used to estimate the noise, so only use it
+    /// on deterministic functions, or they will look noise-free to the optimizer.
+    fn cached(self, capacity: usize) -> Cached<Self> {
+        Cached {
+            source: self,
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0
+        }
+    }
+
+    /// Creates a new [`Target`] that counts evaluations, see [Counted].
+    fn counted(self) -> Counted<Self> {
+        Counted {
+            source: self,
+            calls: 0,
+            nans: 0,
+            elapsed: Duration::ZERO
+        }
+    }
+
+    /// Creates a new [`Target`] that records every evaluation, see [Record].
+    fn record(self) -> Record<Self> {
+        Record {
+            source: self,
+            history: Vec::new(),
+            iteration: 0,
+            role: Role::WarmUp
+        }
+    }
+
+    /// Creates a new [`Target`] that evaluates this function at most `evaluations` times, see [Budget].
+    fn budget(self, evaluations: usize) -> Budget<Self> {
+        Budget {
+            source: self,
+            remaining: evaluations
+        }
+    }
 }
 
 impl<T: Target> TargetExt for T {}
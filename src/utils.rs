@@ -1,6 +1,8 @@
 use crate::vec::vectorize;
-use rand::Rng;
-use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng, thread_rng};
+use rand_chacha::ChaCha12Rng;
+use crate::Perturbation;
+use crate::vec::op;
 use rand::distributions::uniform::SampleRange;
 use packed_simd_2::f64x4;
 
@@ -38,7 +40,7 @@ pub fn cosine(lhs: &[f64], rhs: &[f64]) -> f64 {
 }
 
 #[inline]
-pub fn rand(rng: &mut StdRng, r: impl SampleRange<f64> + Clone) -> f64x4 {
+pub fn rand<R: Rng + ?Sized>(rng: &mut R, r: impl SampleRange<f64> + Clone) -> f64x4 {
     f64x4::new(
         rng.gen_range(r.clone()),
         rng.gen_range(r.clone()),
@@ -48,7 +50,7 @@ pub fn rand(rng: &mut StdRng, r: impl SampleRange<f64> + Clone) -> f64x4 {
 }
 
 #[inline]
-pub fn randsign(rng: &mut StdRng) -> f64x4 {
+pub fn randsign<R: Rng + ?Sized>(rng: &mut R) -> f64x4 {
     fn sign(b: bool) -> f64 {
         if b {
             1.0
@@ -65,6 +67,58 @@ pub fn randsign(rng: &mut StdRng) -> f64x4 {
     )
 }
 
+/// Standard normal samples, using the Box-Muller transform
+#[inline]
+pub fn randn<R: Rng + ?Sized>(rng: &mut R) -> f64x4 {
+    let u = rand(rng, f64::EPSILON..1.0);
+    let v = rand(rng, 0.0..1.0);
+
+    let r = (-2.0 * u.ln()).sqrt();
+    let t = v * (2.0 * std::f64::consts::PI);
+    r * t.cos()
+}
+
+/// Fill `v` with a perturbation, `draw` is the number of perturbations drawn before
+pub fn perturb<R: Rng + ?Sized>(perturbation: Perturbation, rng: &mut R, draw: usize, v: &mut [f64]) {
+    match perturbation {
+        Perturbation::Rademacher => {
+            op!(mut v => randsign(rng));
+        },
+        Perturbation::SegmentedUniform(min, max) => {
+            op!(mut v => randsign(rng) * rand(rng, min..=max));
+        },
+        Perturbation::Bernoulli(magnitude) => {
+            op!(mut v => randsign(rng) * magnitude);
+        },
+        Perturbation::Gaussian => {
+            op!(mut v => randn(rng));
+        },
+        Perturbation::Hadamard => {
+            // rows of a Sylvester-type Hadamard matrix, skipping the column of ones
+            let rows = (v.len() + 1).next_power_of_two();
+            let row = draw % rows;
+
+            for (i, v) in v.iter_mut().enumerate() {
+                *v = if (row & (i + 1)).count_ones() & 1 == 0 { 1.0 } else { -1.0 };
+            }
+        }
+    }
+}
+
+#[inline]
+pub fn clamp(arr: &mut [f64], bounds: &[(f64, f64)]) {
+    for (v, &(lo, hi)) in arr.iter_mut().zip(bounds) {
+        *v = v.max(lo).min(hi);
+    }
+}
+
+pub fn seeded(seed: Option<u64>) -> ChaCha12Rng {
+    match seed {
+        Some(seed) => ChaCha12Rng::seed_from_u64(seed),
+        None => ChaCha12Rng::from_rng(thread_rng()).unwrap()
+    }
+}
+
 #[inline]
 pub fn nz(f: f64) -> f64 {
     if f.is_nan() {
@@ -74,6 +128,65 @@ pub fn nz(f: f64) -> f64 {
     }
 }
 
+/// Eigendecomposition of a symmetric `n × n` row-major matrix by cyclic Jacobi rotations.
+/// `a` is diagonalized in place, leaving the eigenvalues on its diagonal, and the eigenvectors are stored in the columns of `v`.
+pub fn jacobi(a: &mut [f64], v: &mut [f64], n: usize) {
+    for i in 0..n {
+        for j in 0..n {
+            v[i * n + j] = if i == j { 1.0 } else { 0.0 };
+        }
+    }
+
+    for _ in 0..50 {
+        let mut off = 0.0;
+        let mut diag = 0.0;
+
+        for i in 0..n {
+            diag += a[i * n + i] * a[i * n + i];
+
+            for j in i + 1..n {
+                off += a[i * n + j] * a[i * n + j];
+            }
+        }
+
+        if off <= 1e-30 * diag {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[p * n + q];
+
+                if apq == 0.0 {
+                    continue;
+                }
+
+                let theta = (a[q * n + q] - a[p * n + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + f64::sqrt(theta * theta + 1.0));
+                let c = 1.0 / f64::sqrt(t * t + 1.0);
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+
+                for k in 0..n {
+                    let (vkp, vkq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -101,6 +214,27 @@ mod tests {
         assert_relative_eq!(dot, 10.0);
     }
 
+    #[test]
+    fn clamp() {
+        let mut a = [-2.0, 0.5, 3.0];
+        super::clamp(&mut a, &[(-1.0, 1.0), (0.0, 1.0), (0.0, 2.0)]);
+        assert_eq!(a, [-1.0, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn jacobi() {
+        let mut a = [2.0, 1.0, 1.0, 2.0];
+        let mut v = [0.0; 4];
+        super::jacobi(&mut a, &mut v, 2);
+
+        let (l0, l1) = (a[0].min(a[3]), a[0].max(a[3]));
+        assert_relative_eq!(l0, 1.0, epsilon = 1e-12);
+        assert_relative_eq!(l1, 3.0, epsilon = 1e-12);
+        assert_relative_eq!(a[1], 0.0, epsilon = 1e-12);
+        assert_relative_eq!(v[0].abs(), f64::sqrt(0.5), epsilon = 1e-12);
+        assert_relative_eq!(v[0] * v[1] + v[2] * v[3], 0.0, epsilon = 1e-12);
+    }
+
     #[test]
     fn cosine() {
         let a = [1.0, 2.0, 3.0];
@@ -1,5 +1,5 @@
 use approx::assert_relative_eq;
-use spsa::{minimize, Optimizer, Options, Target, TargetExt};
+use spsa::{minimize, AdaBelief, AdaGrad, Adam, AugmentedLagrangian, BatchTarget, ConstrainedTarget, Checkpoint, Control, DiagonalHessian, Direction, Iteration, MaxEvaluations, Method, Optimizer, Options, ParamKind, ParamSpace, Perturbation, Preconditioner, Role, SpsaError, SpsaState, StopReason, Target, TargetExt, TargetValue};
 use rand::{Rng, SeedableRng, thread_rng};
 use rand::rngs::StdRng;
 
@@ -13,7 +13,7 @@ fn simple_fn() {
     let mut optimizer = Optimizer::new();
     let mut input = [0.0, 0.0];
 
-    optimizer.optimize(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default());
+    optimizer.optimize(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default()).unwrap();
 
     assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
     assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
@@ -35,12 +35,311 @@ fn noisy_fn() {
     let mut optimizer = Optimizer::new();
     let mut input = [0.0, 0.0];
 
-    optimizer.optimize(NoisyFunction(StdRng::from_rng(thread_rng()).unwrap()).oversample(2),&mut input, Options::default());
+    optimizer.optimize(NoisyFunction(StdRng::from_rng(thread_rng()).unwrap()).oversample(2),&mut input, Options::default()).unwrap();
 
     assert_relative_eq!(input[0], -1.0, epsilon = 1e-2);
     assert_relative_eq!(input[1],  1.0, epsilon = 1e-2);
 }
 
+#[test]
+fn gradient_samples() {
+    pub struct NoisyFunction(StdRng);
+
+    impl Target for NoisyFunction {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            let x = data[0];
+            let y = data[1];
+
+            1.0 - (x + 1.0) * (x + 1.0) - (y - 1.0) * (y - 1.0) + self.0.gen_range(-0.001..0.001)
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        gradient_samples: 4,
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(NoisyFunction(StdRng::from_rng(thread_rng()).unwrap()), &mut input, options).unwrap();
+
+    assert!(report.evaluations > 8 * report.iterations);
+    assert_relative_eq!(input[0], -1.0, epsilon = 1e-2);
+    assert_relative_eq!(input[1],  1.0, epsilon = 1e-2);
+}
+
+#[test]
+fn deterministic() {
+    pub struct Bowl;
+
+    impl Target for Bowl {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            -(data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn is_deterministic(&self) -> bool {
+            true
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        one_sided: true,
+        line_search: false,
+        seed: Some(42),
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(Bowl, &mut input, options).unwrap();
+
+    assert_eq!(report.restarts, 0);
+    assert!(report.evaluations <= 2 * report.iterations + 50);
+    assert!(report.value <= 0.0 && report.value > -1e-10);
+    assert!(report.learning_rate.is_finite());
+    assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
+    assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
+}
+
+#[test]
+fn bounded_steps() {
+    pub struct Bowl {
+        deterministic: bool,
+        growth: f64,
+        point: Vec<f64>,
+        step: f64,
+        restarts: usize
+    }
+
+    impl Target for Bowl {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            -(data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn is_deterministic(&self) -> bool {
+            self.deterministic
+        }
+
+        fn iteration(&mut self, iter: Iteration) -> Control {
+            let step = f64::hypot(iter.point[0] - self.point[0], iter.point[1] - self.point[1]);
+
+            // restarts jump back to the best point, and rounding errors blur the smallest steps
+            if iter.improvement_fails == self.restarts && self.step > 0.0 {
+                assert!(step <= self.growth * (self.step + 1e-15), "step grew from {} to {}", self.step, step);
+            }
+
+            self.point.copy_from_slice(iter.point);
+            self.step = step;
+            self.restarts = iter.improvement_fails;
+
+            Control::Continue
+        }
+    }
+
+    // one-sided gradients and steps tuned without line search can't tell when the gradient estimate has gone wrong,
+    // so their steps may at most double from one iteration to the next, while line search lets them grow 16-fold
+    for &(one_sided, line_search, deterministic) in &[(true, false, true), (true, true, true), (true, true, false), (false, false, true), (false, false, false), (false, true, true), (false, true, false)] {
+        let growth = if one_sided || !line_search { 2.0 } else { 16.0 };
+
+        for seed in 0..4 {
+            let mut optimizer = Optimizer::new();
+            let mut input = [0.0, 0.0];
+            let mut target = Bowl { deterministic, growth, point: input.to_vec(), step: 0.0, restarts: 0 };
+
+            let options = Options {
+                one_sided,
+                line_search,
+                seed: Some(seed),
+                ..Options::default()
+            };
+
+            let report = optimizer.optimize(&mut target, &mut input, options).unwrap();
+
+            assert!(report.value.is_finite() && report.learning_rate.is_finite());
+        }
+    }
+}
+
+#[test]
+fn moved_point() {
+    pub struct Bowl;
+
+    impl Target for Bowl {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            -(data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn is_deterministic(&self) -> bool {
+            true
+        }
+
+        fn iteration(&mut self, iter: Iteration) -> Control {
+            if iter.iteration == 200 {
+                iter.point[0] += 5.0;
+            }
+
+            Control::Continue
+        }
+    }
+
+    // one-sided perturbations are compared against the value at the moved point, not the one evaluated before
+    for &one_sided in &[true, false] {
+        let mut optimizer = Optimizer::new();
+        let mut input = [0.0, 0.0];
+
+        let options = Options {
+            one_sided,
+            seed: Some(3),
+            ..Options::default()
+        };
+
+        let report = optimizer.optimize(Bowl, &mut input, options).unwrap();
+
+        assert!(report.value > -1e-6);
+        assert_relative_eq!(input[0], -1.0, epsilon = 1e-3);
+        assert_relative_eq!(input[1],  1.0, epsilon = 1e-3);
+    }
+}
+
+#[test]
+fn cached() {
+    use std::cell::Cell;
+
+    let calls = Cell::new(0);
+    let mut target = minimize(|data: &[f64]| {
+        calls.set(calls.get() + 1);
+        1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)
+    }).cached(16);
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        seed: Some(42),
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(&mut target, &mut input, options).unwrap();
+
+    assert!(target.hits() > report.iterations);
+    assert_eq!(target.hits() + target.misses(), report.evaluations);
+    assert_eq!(target.misses(), calls.get());
+
+    assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
+    assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
+}
+
+#[test]
+fn counted() {
+    let mut target = minimize(|data| {
+        if data[0] > 0.0 {
+            return f64::NAN;
+        }
+
+        1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)
+    }).counted();
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [-0.5, 0.0];
+
+    let report = optimizer.optimize(&mut target, &mut input, Options::default()).unwrap();
+
+    assert_eq!(target.calls(), report.evaluations);
+    assert!(target.nans() > 0);
+    assert!(target.elapsed() > std::time::Duration::ZERO);
+}
+
+#[test]
+fn budget() {
+    let mut target = minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)).budget(1000);
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        seed: Some(42),
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(&mut target, &mut input, options).unwrap();
+
+    assert_eq!(report.stop, StopReason::Evaluations);
+    assert!(report.evaluations <= 1000);
+    assert!(report.value.is_finite());
+    assert_eq!(target.remaining(), 1000 - report.evaluations);
+}
+
+#[test]
+fn small_budget() {
+    for evaluations in [0, 1, 5, 30, 100] {
+        let mut target = minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)).budget(evaluations);
+
+        let mut optimizer = Optimizer::new();
+        let mut input = [0.0, 0.0];
+
+        let options = Options {
+            seed: Some(42),
+            ..Options::default()
+        };
+
+        let report = optimizer.optimize(&mut target, &mut input, options).unwrap();
+
+        assert_eq!(report.stop, StopReason::Evaluations);
+        assert_eq!(target.remaining(), evaluations - report.evaluations);
+        assert!(target.remaining() < 10, "{} evaluations left out of {}", target.remaining(), evaluations);
+        assert!(input.iter().all(|v| v.is_finite()));
+
+        if evaluations >= 2 { // enough to evaluate the initial point
+            assert!(report.value.is_finite());
+        }
+    }
+}
+
+#[test]
+fn record() {
+    let mut target = minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)).record();
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        iterations: 10,
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(&mut target, &mut input, options).unwrap();
+    let history = target.history();
+
+    assert_eq!(history.len(), report.evaluations);
+    assert_eq!(history[0].role, Role::WarmUp);
+    assert!(history.windows(2).all(|w| w[0].iteration <= w[1].iteration));
+
+    for &(role, count) in [(Role::PerturbationPlus, 1), (Role::PerturbationMinus, 1), (Role::LineSearch, 2), (Role::Accept, 2)].iter() {
+        assert_eq!(history.iter().filter(|e| e.role == role && e.iteration == 5).count(), count);
+    }
+
+    let mut csv = Vec::new();
+    target.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+
+    assert!(csv.starts_with("iteration,role,value,x0,x1\n"));
+    assert_eq!(csv.lines().count(), history.len() + 1);
+
+    let mut jsonl = Vec::new();
+    target.write_jsonl(&mut jsonl).unwrap();
+
+    for (line, e) in String::from_utf8(jsonl).unwrap().lines().zip(history) {
+        let json: serde_json::Value = serde_json::from_str(line).unwrap();
+
+        assert_eq!(json["iteration"], e.iteration);
+        assert_eq!(json["role"], e.role.to_string());
+        assert_eq!(json["value"].as_f64(), Some(e.value));
+        assert_eq!(json["point"][1].as_f64(), Some(e.point[1]));
+    }
+}
+
 #[test]
 fn bounded_fn() {
     pub struct BoundedFunction;
@@ -63,7 +362,837 @@ fn bounded_fn() {
     let mut optimizer = Optimizer::new();
     let mut input = [0.0, 0.0];
 
-    optimizer.optimize(BoundedFunction,&mut input, Options::default());
+    optimizer.optimize(BoundedFunction,&mut input, Options::default()).unwrap();
 
     assert_relative_eq!(BoundedFunction.evaluate(&input), 10.0, epsilon = 1e-2);
-}
\ No newline at end of file
+}
+
+#[test]
+fn report() {
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+    let mut calls = 0;
+
+    let report = optimizer.optimize(minimize(|data| {
+        calls += 1;
+        1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)
+    }), &mut input, Options::default()).unwrap();
+
+    assert_eq!(report.evaluations, calls);
+    assert!(report.iterations <= Options::default().iterations);
+    assert!(report.learning_rate > 0.0);
+    assert_relative_eq!(report.value, -1.0, epsilon = 1e-6);
+}
+
+#[test]
+fn infeasible_start() {
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let result = optimizer.optimize(minimize(|_| f64::NAN), &mut input, Options::default());
+
+    assert_eq!(result, Err(SpsaError::InfeasibleStart));
+    assert_eq!(input, [0.0, 0.0]);
+}
+
+#[test]
+fn stop_criterion() {
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        stop: Some(Box::new(MaxEvaluations(1000))),
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)), &mut input, options).unwrap();
+
+    assert_eq!(report.stop, StopReason::Evaluations);
+    assert!(report.evaluations >= 1000 && report.evaluations < 1010);
+}
+
+#[test]
+fn direction() {
+    pub struct Bowl {
+        gradient: Vec<f64>
+    }
+
+    impl Target for Bowl {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn iteration(&mut self, iter: Iteration) -> Control {
+            if self.gradient.is_empty() {
+                self.gradient.extend_from_slice(iter.gradient);
+            }
+
+            Control::Continue
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+    let mut target = Bowl { gradient: vec![] };
+
+    let options = Options {
+        direction: Direction::Minimize,
+        stop: Some(Box::new(TargetValue(1.01))),
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(&mut target, &mut input, options).unwrap();
+
+    assert_eq!(report.stop, StopReason::TargetValue);
+    assert!(report.value >= 1.0 && report.value <= 1.1);
+    assert!(target.gradient[0] > 0.0 && target.gradient[1] < 0.0);
+}
+
+#[test]
+fn interrupted() {
+    pub struct Interrupt;
+
+    impl Target for Interrupt {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            -(data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn iteration(&mut self, iter: Iteration) -> Control {
+            if iter.iteration == 9 {
+                Control::Stop
+            } else {
+                Control::Continue
+            }
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        seed: Some(42),
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(Interrupt, &mut input, options).unwrap();
+
+    assert_eq!(report.stop, StopReason::Interrupted);
+    assert_eq!(report.iterations, 10);
+}
+
+#[test]
+fn iteration_data() {
+    pub struct Bowl {
+        evaluations: usize,
+        best: f64,
+        average: Vec<f64>
+    }
+
+    impl Target for Bowl {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn iteration(&mut self, iter: Iteration) -> Control {
+            assert!(iter.evaluations > self.evaluations);
+            assert!(iter.best <= self.best);
+            assert!(iter.value >= 1.0 && iter.best >= 1.0);
+            assert!(iter.noise >= 0.0 && iter.perturbation > 0.0);
+            assert!(iter.momentum > 0.0 && iter.momentum < 1.0);
+
+            self.evaluations = iter.evaluations;
+            self.best = iter.best;
+            self.average = iter.average.to_vec();
+
+            Control::Continue
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+    let mut target = Bowl { evaluations: 0, best: f64::INFINITY, average: vec![] };
+
+    let options = Options {
+        direction: Direction::Minimize,
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(&mut target, &mut input, options).unwrap();
+
+    assert!(target.evaluations <= report.evaluations);
+    assert!((target.average[0] + 1.0).abs() < 0.1 && (target.average[1] - 1.0).abs() < 0.1);
+}
+
+#[test]
+fn ask_tell() {
+    let mut state = SpsaState::new(&[0.0, 0.0], Options::default()).unwrap();
+    let mut calls = 0;
+    let mut iterations = 0;
+
+    while let Some(point) = state.ask() {
+        let value = -(point[0] + 1.0) * (point[0] + 1.0) - (point[1] - 1.0) * (point[1] - 1.0);
+        calls += 1;
+
+        state.tell(value).unwrap();
+
+        if state.iteration().is_some() {
+            iterations += 1;
+        }
+    }
+
+    let report = state.report().unwrap();
+
+    assert_eq!(report.evaluations, calls);
+    assert_eq!(report.iterations, iterations);
+    assert_relative_eq!(state.point()[0], -1.0, epsilon = 1e-6);
+    assert_relative_eq!(state.point()[1],  1.0, epsilon = 1e-6);
+}
+
+#[test]
+fn batch() {
+    pub struct Batched(usize);
+
+    impl BatchTarget for Batched {
+        fn evaluate_batch(&mut self, points: &[&[f64]], out: &mut [f64]) {
+            self.0 += points.len();
+
+            for (point, out) in points.iter().zip(out) {
+                *out = -(point[0] + 1.0) * (point[0] + 1.0) - (point[1] - 1.0) * (point[1] - 1.0);
+            }
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+    let mut target = Batched(0);
+
+    let report = optimizer.optimize_batch(&mut target, &mut input, Options::default()).unwrap();
+
+    assert_eq!(report.evaluations, target.0);
+    assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
+    assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
+}
+
+#[test]
+fn seeded() {
+    fn run() -> ([f64; 2], spsa::Report) {
+        let mut optimizer = Optimizer::new();
+        let mut input = [0.0, 0.0];
+
+        let options = Options {
+            seed: Some(42),
+            ..Options::default()
+        };
+
+        let target = minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0))
+            .input_noise_seeded(0.1, 1)
+            .output_noise_seeded(0.01, 2);
+
+        let report = optimizer.optimize(target, &mut input, options).unwrap();
+        (input, report)
+    }
+
+    let (a, ra) = run();
+    let (b, rb) = run();
+
+    assert_eq!(a[0].to_bits(), b[0].to_bits());
+    assert_eq!(a[1].to_bits(), b[1].to_bits());
+    assert_eq!(ra, rb);
+}
+
+#[test]
+fn custom_rng() {
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+    let mut rng = StdRng::seed_from_u64(7);
+
+    optimizer.optimize_with_rng(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)), &mut input, Options::default(), &mut rng).unwrap();
+
+    assert_relative_eq!(input[0], -1.0, epsilon = 1e-6);
+    assert_relative_eq!(input[1],  1.0, epsilon = 1e-6);
+}
+
+#[test]
+fn common_random_numbers() {
+    pub struct Simulation;
+
+    impl Target for Simulation {
+        fn evaluate(&mut self, _: &[f64]) -> f64 {
+            unreachable!()
+        }
+
+        fn evaluate_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+            let x = data[0];
+            let y = data[1];
+
+            1.0 - (x + 1.0) * (x + 1.0) - (y - 1.0) * (y - 1.0) + StdRng::seed_from_u64(seed).gen_range(-0.1..0.1)
+        }
+    }
+
+    let mut state = SpsaState::new(&[0.0, 0.0], Options::default()).unwrap();
+    let mut seeds = Vec::new();
+
+    while let Some(point) = state.ask() {
+        let point = point.to_vec();
+        let seed = state.seed();
+
+        let value = match seed {
+            Some(seed) => Simulation.evaluate_seeded(&point, seed),
+            None => 1.0 - (point[0] + 1.0) * (point[0] + 1.0) - (point[1] - 1.0) * (point[1] - 1.0)
+        };
+
+        seeds.extend(seed);
+        state.tell(value).unwrap();
+    }
+
+    assert!(seeds.chunks(2).all(|pair| pair[0] == pair[1]));
+    assert_relative_eq!(state.point()[0], -1.0, epsilon = 1e-2);
+    assert_relative_eq!(state.point()[1],  1.0, epsilon = 1e-2);
+}
+
+#[test]
+fn seeded_noise() {
+    let mut target = minimize(|data| (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0))
+        .input_noise(0.1)
+        .output_noise(0.01);
+
+    let point = [0.5, 0.5];
+    let a = target.evaluate_seeded(&point, 7);
+    let b = target.evaluate_seeded(&point, 7);
+    let c = target.evaluate_seeded(&point, 8);
+
+    assert_eq!(a.to_bits(), b.to_bits());
+    assert_ne!(a.to_bits(), c.to_bits());
+    assert_ne!(target.evaluate(&point).to_bits(), target.evaluate(&point).to_bits());
+}
+
+#[test]
+fn checkpoint() {
+    pub struct Saving(Option<Checkpoint>);
+
+    impl Target for Saving {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            -(data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn checkpoint(&mut self, checkpoint: Checkpoint) {
+            if self.0.is_none() {
+                self.0 = Some(checkpoint);
+            }
+        }
+    }
+
+    fn options() -> Options {
+        Options {
+            seed: Some(3),
+            checkpoint_interval: Some(100),
+            ..Options::default()
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut target = Saving(None);
+    let mut a = [0.0, 0.0];
+    let ra = optimizer.optimize(&mut target, &mut a, options()).unwrap();
+
+    let checkpoint = target.0.take().unwrap();
+
+    #[cfg(feature = "serde")]
+    let checkpoint = serde_json::from_str(&serde_json::to_string(&checkpoint).unwrap()).unwrap();
+
+    let mut b = [0.0, 0.0];
+    let rb = optimizer.resume(checkpoint, &mut target, &mut b, options()).unwrap();
+
+    assert_eq!(a[0].to_bits(), b[0].to_bits());
+    assert_eq!(a[1].to_bits(), b[1].to_bits());
+    assert_eq!(ra, rb);
+}
+
+#[test]
+fn resume_failed() {
+    let mut state = SpsaState::new(&[0.0, 0.0], Options::default()).unwrap();
+
+    while state.ask().is_some() {
+        if state.tell(f64::NAN).is_err() {
+            break;
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let result = optimizer.resume(state.checkpoint(), minimize(|data| data[0] + data[1]), &mut input, Options::default());
+
+    assert_eq!(result, Err(SpsaError::Unfinished));
+}
+
+#[test]
+fn box_bounds() {
+    let mut optimizer = Optimizer::new();
+    let mut input = [1.0, 0.0];
+
+    let options = Options {
+        bounds: Some(vec![(0.0, 2.0), (0.0, 0.5)]),
+        ..Options::default()
+    };
+
+    optimizer.optimize(minimize(|data| {
+        assert!((0.0..=2.0).contains(&data[0]) && (0.0..=0.5).contains(&data[1]));
+        1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0)
+    }), &mut input, options).unwrap();
+
+    assert_relative_eq!(input[0], 0.0, epsilon = 1e-6);
+    assert_relative_eq!(input[1], 0.5, epsilon = 1e-6);
+}
+
+#[test]
+fn discrete() {
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = Options {
+        kinds: Some(vec![ParamKind::Integer, ParamKind::Categorical(4)]),
+        ..Options::default()
+    };
+
+    optimizer.optimize(minimize(|data| {
+        assert_eq!(data[0].fract(), 0.0);
+        assert!([0.0, 1.0, 2.0, 3.0].contains(&data[1]));
+        1.0 + (data[0] - 4.3) * (data[0] - 4.3) + (data[1] - 5.0) * (data[1] - 5.0)
+    }), &mut input, options).unwrap();
+
+    assert_eq!(input[0], 4.0);
+    assert_eq!(input[1], 3.0);
+}
+
+#[test]
+fn discrete_bounds() {
+    let mut optimizer = Optimizer::new();
+    let mut input = [1.0, 2.0];
+
+    let options = Options {
+        bounds: Some(vec![(0.5, 2.5), (1.2, 5.0)]),
+        kinds: Some(vec![ParamKind::Integer, ParamKind::Categorical(4)]),
+        seed: Some(42),
+        ..Options::default()
+    };
+
+    optimizer.optimize(minimize(|data| {
+        assert!([1.0, 2.0].contains(&data[0]));
+        assert!([2.0, 3.0].contains(&data[1]));
+        1.0 + (data[0] - 4.3) * (data[0] - 4.3) + data[1] * data[1]
+    }), &mut input, options).unwrap();
+
+    assert_eq!(input, [2.0, 2.0]);
+
+    let options = Options {
+        bounds: Some(vec![(0.2, 0.8), (0.0, 1.0)]),
+        kinds: Some(vec![ParamKind::Integer, ParamKind::Continuous]),
+        ..Options::default()
+    };
+
+    let result = optimizer.optimize(minimize(|data| data[0] * data[0] + data[1] * data[1]), &mut input, options);
+    assert_eq!(result, Err(SpsaError::InvalidBounds));
+}
+
+#[test]
+fn param_space() {
+    let space = ParamSpace::new()
+        .param("rate", 1e-2).log_scale().bounds(1e-3, 1.0)
+        .param("width", 100.0).scale(100.0).bounds(0.0, 1000.0);
+
+    // rate ends up at its lower bound, where its gradient does not vanish and keeps adding noise
+    // to the gradient estimates of width, so some seeds end up further from the optimum than 1e-3
+    let params = Optimizer::new().optimize_space(minimize(|data| {
+        assert!((1e-3..=1.0).contains(&data[0]) && (0.0..=1000.0).contains(&data[1]));
+
+        let rate = data[0].ln() - f64::ln(1e-4);
+        let width = (data[1] - 300.0) / 100.0;
+        1.0 + rate * rate + width * width
+    }), &space, Options { seed: Some(42), ..Options::default() }).unwrap();
+
+    assert_eq!(params.names, ["rate", "width"]);
+    assert_relative_eq!(params.get("rate").unwrap(), 1e-3, max_relative = 1e-6);
+    assert_relative_eq!(params["width"], 300.0, epsilon = 1e-3);
+    assert_eq!(params.get("depth"), None);
+}
+
+#[test]
+fn param_space_options() {
+    let space = ParamSpace::new().param("x", 0.0);
+    let target = || minimize(|data| 1.0 + (data[0] - 3.0) * (data[0] - 3.0));
+
+    // bounds of the caller would apply to normalized coordinates, so they are replaced even by an unbounded space
+    let options = Options {
+        bounds: Some(vec![(-0.1, 0.1)]),
+        seed: Some(42),
+        ..Options::default()
+    };
+
+    let params = Optimizer::new().optimize_space(target(), &space, options).unwrap();
+    assert_relative_eq!(params["x"], 3.0, epsilon = 1e-6);
+
+    let options = Options {
+        kinds: Some(vec![ParamKind::Integer]),
+        ..Options::default()
+    };
+
+    assert_eq!(Optimizer::new().optimize_space(target(), &space, options), Err(SpsaError::InvalidKinds));
+}
+
+#[test]
+#[should_panic(expected = "positive initial value")]
+fn param_space_log_scale() {
+    let _ = ParamSpace::new().param("rate", 0.0).log_scale();
+}
+
+#[test]
+fn preconditioners() {
+    let preconditioners: Vec<Option<Box<dyn Preconditioner>>> = vec![
+        None,
+        Some(Box::new(AdaGrad)),
+        Some(Box::new(AdaBelief)),
+        Some(Box::new(DiagonalHessian))
+    ];
+
+    for preconditioner in preconditioners {
+        let mut optimizer = Optimizer::new();
+        let mut input = [0.0, 0.0];
+
+        let options = Options {
+            preconditioner,
+            seed: Some(42),
+            ..Options::default()
+        };
+
+        optimizer.optimize(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + 100.0 * (data[1] - 1.0) * (data[1] - 1.0)), &mut input, options).unwrap();
+
+        assert_relative_eq!(input[0], -1.0, epsilon = 1e-4);
+        assert_relative_eq!(input[1],  1.0, epsilon = 1e-4);
+    }
+
+    // moving averages are scaled along with their bias on restarts, while the sum of AdaGrad is kept
+    let mut stats = [4.0, 2.0];
+    Adam.restart(&mut stats, 0.5);
+    assert_eq!(stats, [2.0, 1.0]);
+
+    AdaGrad.restart(&mut stats, 0.5);
+    assert_eq!(stats, [2.0, 1.0]);
+}
+
+#[test]
+fn perturbations() {
+    let perturbations = [
+        Perturbation::Rademacher,
+        Perturbation::SegmentedUniform(0.5, 1.5),
+        Perturbation::Bernoulli(0.5),
+        Perturbation::Gaussian,
+        Perturbation::Hadamard
+    ];
+
+    for &perturbation in &perturbations {
+        let mut optimizer = Optimizer::new();
+        let mut input = [0.0, 0.0, 0.0];
+
+        let options = Options {
+            perturbation,
+            seed: Some(42),
+            ..Options::default()
+        };
+
+        optimizer.optimize(minimize(|data| 1.0 + (data[0] + 1.0) * (data[0] + 1.0) + (data[1] - 1.0) * (data[1] - 1.0) + data[2] * data[2]), &mut input, options).unwrap();
+
+        assert_relative_eq!(input[0], -1.0, epsilon = 1e-4);
+        assert_relative_eq!(input[1],  1.0, epsilon = 1e-4);
+        assert_relative_eq!(input[2],  0.0, epsilon = 1e-4);
+    }
+
+    let invalid = [
+        Perturbation::SegmentedUniform(1.5, 0.5),
+        Perturbation::SegmentedUniform(0.0, 1.0),
+        Perturbation::SegmentedUniform(f64::NAN, 1.0),
+        Perturbation::Bernoulli(-1.0)
+    ];
+
+    for &perturbation in &invalid {
+        let mut optimizer = Optimizer::new();
+        let mut input = [0.0, 0.0];
+
+        let options = Options {
+            perturbation,
+            ..Options::default()
+        };
+
+        let result = optimizer.optimize(minimize(|data| data[0] * data[0] + data[1] * data[1]), &mut input, options);
+        assert_eq!(result, Err(SpsaError::InvalidPerturbation));
+    }
+}
+
+#[test]
+fn invalid_options() {
+    let invalid = vec![
+        (Options { bounds: Some(vec![(0.0, 1.0)]), ..Options::default() }, SpsaError::InvalidBounds),
+        (Options { bounds: Some(vec![(0.0, 1.0), (1.0, 0.0)]), ..Options::default() }, SpsaError::InvalidBounds),
+        (Options { bounds: Some(vec![(f64::NAN, 1.0), (0.0, 1.0)]), ..Options::default() }, SpsaError::InvalidBounds),
+        (Options { kinds: Some(vec![ParamKind::Integer; 3]), ..Options::default() }, SpsaError::InvalidKinds),
+        (Options { kinds: Some(vec![ParamKind::Integer; 2]), one_sided: true, ..Options::default() }, SpsaError::InvalidKinds),
+        (Options { gradient_samples: 0, ..Options::default() }, SpsaError::NoGradientSamples)
+    ];
+
+    let mut optimizer = Optimizer::new();
+
+    for (options, error) in invalid {
+        let mut input = [0.5, 0.5];
+
+        let result = optimizer.optimize(minimize(|data| data[0] * data[0] + data[1] * data[1]), &mut input, options);
+
+        assert_eq!(result, Err(error));
+        assert_eq!(input, [0.5, 0.5]);
+    }
+
+    let options = Options { gradient_samples: 0, ..Options::default() };
+    assert_eq!(SpsaState::new(&[0.0, 0.0], options).err(), Some(SpsaError::NoGradientSamples));
+
+    let mut input = [0.5, 0.5];
+    optimizer.optimize(minimize(|data| data[0] * data[0] + data[1] * data[1]), &mut input, Options::default()).unwrap();
+
+    assert_relative_eq!(input[0], 0.0, epsilon = 1e-6);
+    assert_relative_eq!(input[1], 0.0, epsilon = 1e-6);
+}
+
+#[test]
+fn second_order() {
+    pub struct Valley;
+
+    impl Target for Valley {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            let (u, v) = (data[0] + data[1] - 1.0, data[0] - data[1]);
+            1.0 + u * u + 100.0 * v * v
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [1.0, 1.0];
+
+    let options = Options {
+        direction: Direction::Minimize,
+        method: Method::SecondOrder,
+        iterations: 2000,
+        seed: Some(42),
+        ..Options::default()
+    };
+
+    let report = optimizer.optimize(Valley, &mut input, options).unwrap();
+
+    let hessian = report.hessian.unwrap();
+
+    assert_relative_eq!(input[0], 0.5, epsilon = 1e-6);
+    assert_relative_eq!(input[1], 0.5, epsilon = 1e-6);
+    assert_relative_eq!(hessian[0], 202.0, max_relative = 0.3);
+    assert_relative_eq!(hessian[1], -198.0, max_relative = 0.3);
+    assert_eq!(hessian[1], hessian[2]);
+}
+
+#[test]
+fn constrained() {
+    pub struct Problem;
+
+    impl ConstrainedTarget for Problem {
+        fn objective(&mut self, data: &[f64]) -> f64 {
+            -data[0] * data[0] - data[1] * data[1]
+        }
+
+        fn inequalities(&self) -> usize {
+            1
+        }
+
+        fn equalities(&self) -> usize {
+            1
+        }
+
+        fn constraints(&mut self, data: &[f64], g: &mut [f64], h: &mut [f64]) {
+            g[0] = 0.7 - data[0];
+            h[0] = data[0] + data[1] - 1.0;
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+
+    let options = || Options { seed: Some(42), ..Options::default() };
+    let report = optimizer.optimize_constrained(Problem, &mut input, options, AugmentedLagrangian::default()).unwrap();
+
+    assert_relative_eq!(input[0], 0.7, epsilon = 1e-3);
+    assert_relative_eq!(input[1], 0.3, epsilon = 1e-3);
+    assert!(report.violation < 1e-3);
+    assert_eq!(report.equalities.len(), 1);
+}
+
+#[test]
+fn constrained_seeded() {
+    pub struct Simulation(Vec<u64>);
+
+    impl ConstrainedTarget for Simulation {
+        fn objective(&mut self, data: &[f64]) -> f64 {
+            -data[0] * data[0] - data[1] * data[1]
+        }
+
+        fn objective_seeded(&mut self, data: &[f64], seed: u64) -> f64 {
+            self.0.push(seed);
+            self.objective(data) + StdRng::seed_from_u64(seed).gen_range(-0.01..0.01)
+        }
+
+        fn inequalities(&self) -> usize {
+            1
+        }
+
+        fn constraints(&mut self, data: &[f64], g: &mut [f64], _: &mut [f64]) {
+            g[0] = 0.7 - data[0];
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    let mut input = [0.0, 0.0];
+    let mut target = Simulation(Vec::new());
+
+    let options = || Options { seed: Some(42), iterations: 1000, ..Options::default() };
+    optimizer.optimize_constrained(&mut target, &mut input, options, AugmentedLagrangian::default()).unwrap();
+
+    assert!(!target.0.is_empty());
+    assert!(target.0.chunks(2).all(|pair| pair[0] == pair[1]));
+    assert_relative_eq!(input[0], 0.7, epsilon = 1e-2);
+    assert_relative_eq!(input[1], 0.0, epsilon = 1e-2);
+}
+
+#[test]
+fn constrained_deterministic() {
+    pub struct Problem(bool, Vec<Role>);
+
+    impl ConstrainedTarget for Problem {
+        fn objective(&mut self, data: &[f64]) -> f64 {
+            -data[0] * data[0] - data[1] * data[1]
+        }
+
+        fn inequalities(&self) -> usize {
+            1
+        }
+
+        fn constraints(&mut self, data: &[f64], g: &mut [f64], _: &mut [f64]) {
+            g[0] = 0.7 - data[0];
+        }
+
+        fn is_deterministic(&self) -> bool {
+            self.0
+        }
+
+        fn evaluating(&mut self, role: Role) {
+            self.1.push(role);
+        }
+    }
+
+    // warm-up evaluations per inner optimization, which deterministic targets make fewer of
+    let warmups = |deterministic| {
+        let mut optimizer = Optimizer::new();
+        let mut input = [0.0, 0.0];
+        let mut target = Problem(deterministic, Vec::new());
+
+        let options = || Options { seed: Some(42), ..Options::default() };
+        let report = optimizer.optimize_constrained(&mut target, &mut input, options, AugmentedLagrangian::default()).unwrap();
+
+        assert_eq!(target.1.len() + 1, report.evaluations);
+        assert_relative_eq!(input[0], 0.7, epsilon = 1e-3);
+
+        target.1.iter().filter(|&&role| role == Role::WarmUp).count() as f64 / report.iterations as f64
+    };
+
+    assert!(warmups(true) < warmups(false));
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing() {
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::{Event, Subscriber};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    /// Records the names of the spans and the messages of the events
+    #[derive(Clone, Default)]
+    pub struct Log(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for Log {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            self.0.lock().unwrap().push(attrs.metadata().name().to_string());
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            event.record(&mut Messages(&mut self.0.lock().unwrap()));
+        }
+    }
+
+    pub struct Messages<'a>(&'a mut Vec<String>);
+
+    impl Visit for Messages<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            if field.name() == "message" {
+                self.0.push(format!("{:?}", value));
+            }
+        }
+    }
+
+    pub struct Bowl {
+        role: Role,
+        iteration: usize
+    }
+
+    impl Target for Bowl {
+        fn evaluate(&mut self, data: &[f64]) -> f64 {
+            if self.iteration == 5 && self.role == Role::Accept { // the first step of the iteration goes out of bounds
+                self.iteration += 1;
+                return f64::NAN;
+            }
+
+            -(data[0] + 1.0) * (data[0] + 1.0) - (data[1] - 1.0) * (data[1] - 1.0)
+        }
+
+        fn iteration(&mut self, iter: Iteration) -> Control {
+            self.iteration = iter.iteration;
+
+            if iter.iteration == 10 {
+                Control::Restart
+            } else {
+                Control::Continue
+            }
+        }
+
+        fn evaluating(&mut self, role: Role) {
+            self.role = role;
+        }
+    }
+
+    let log = Log::default();
+    let subscriber = tracing_subscriber::registry().with(log.clone());
+
+    let report = tracing::subscriber::with_default(subscriber, || {
+        let mut input = [0.0, 0.0];
+
+        let options = Options {
+            iterations: 20,
+            seed: Some(42),
+            ..Options::default()
+        };
+
+        Optimizer::new().optimize(Bowl { role: Role::WarmUp, iteration: 0 }, &mut input, options).unwrap()
+    });
+
+    let log = log.0.lock().unwrap();
+    let position = |name: &str| log.iter().position(|entry| entry == name).unwrap_or_else(|| panic!("{} not in {:?}", name, log));
+
+    assert_eq!(report.restarts, 1);
+    assert!(position("warm_up") < position("lr_search"));
+    assert!(position("lr_search") < position("main"));
+    assert!(position("main") < position("step went out of bounds, rolling back"));
+    assert!(position("step went out of bounds, rolling back") < position("restart"));
+}